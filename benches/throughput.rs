@@ -0,0 +1,167 @@
+// Measures how many requests a second the server answers for a
+// small static file, once opening a fresh connection for every
+// request and once reusing a keep-alive connection.
+//
+// Run it with `cargo bench`. Criterion times each case over many
+// samples and says how much it's changed since the last run, and
+// whether that's more than noise, so a slowdown shows up as one.
+// `cargo bench -- --save-baseline before` on one commit and
+// `cargo bench -- --baseline before` on another compares the two.
+//
+// BENCH_SECONDS sets how long each case is measured for (default 3)
+// and BENCH_CLIENTS how many clients hammer the server at once
+// (default 4). BENCH_LOG_LEVEL=info turns the access log on, to see
+// what logging costs; send stderr somewhere if so.
+//
+// On a 4 thread laptop-class machine, adding keep-alive and writing
+// responses through one buffered write took this from
+//
+//   new connection per request:  ~22,000 requests/sec
+//                   keep-alive:  ~22,000 requests/sec (no reuse yet)
+//
+// to
+//
+//   new connection per request:  ~26,000 requests/sec
+//                   keep-alive:  ~70,000 requests/sec
+//
+use std::env;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use server::log::Level;
+use server::{Config, Server};
+
+fn throughput(c: &mut Criterion) {
+    let seconds = env_or("BENCH_SECONDS", 3);
+    let clients = env_or("BENCH_CLIENTS", 4);
+    let addr = start_server();
+
+    let mut group = c.benchmark_group(format!("GET / with {} clients", clients));
+    group.measurement_time(Duration::from_secs(seconds));
+    // One element is one request, so criterion reports requests a
+    // second as well as the time each takes.
+    group.throughput(Throughput::Elements(1));
+    for keep_alive in [false, true] {
+        let name = if keep_alive {
+            "keep-alive"
+        } else {
+            "new connection per request"
+        };
+        group.bench_function(name, |b| {
+            b.iter_custom(|requests| run(&addr, clients, requests, keep_alive))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
+
+fn env_or(name: &str, default: u64) -> u64 {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+// Starts a server on a free port in the background and returns its
+// address.
+fn start_server() -> String {
+    let log_level = env::var("BENCH_LOG_LEVEL")
+        .ok()
+        .and_then(|name| Level::from_name(&name))
+        .unwrap_or(Level::Warn);
+    let config = Config {
+        // Port 0 makes the OS pick a free port.
+        addr: String::from("127.0.0.1:0"),
+        log_level,
+        ..Config::default()
+    };
+    let bound = Server::new(config).bind().unwrap();
+    let addr = bound.local_addr().unwrap().to_string();
+    thread::spawn(move || bound.run().unwrap());
+    addr
+}
+
+// Has clients threads send requests between them, as close to
+// evenly as they divide, and returns how long it took.
+fn run(addr: &str, clients: u64, requests: u64, keep_alive: bool) -> Duration {
+    let started = Instant::now();
+
+    let threads: Vec<_> = (0..clients)
+        .map(|i| {
+            let addr = addr.to_string();
+            let share = requests / clients + u64::from(i < requests % clients);
+            thread::spawn(move || client(&addr, share, keep_alive))
+        })
+        .collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    started.elapsed()
+}
+
+// Sends requests requests, one after another, each waiting for the
+// answer to the last. If the server closes a keep-alive connection
+// we just open another.
+fn client(addr: &str, requests: u64, keep_alive: bool) {
+    let request: &[u8] = if keep_alive {
+        b"GET / HTTP/1.1\r\nHost: bench\r\n\r\n"
+    } else {
+        b"GET / HTTP/1.1\r\nHost: bench\r\nConnection: close\r\n\r\n"
+    };
+
+    let mut answered = 0;
+    let mut connection: Option<BufReader<TcpStream>> = None;
+
+    while answered < requests {
+        let reader = match connection.as_mut() {
+            Some(reader) => reader,
+            None => connection.insert(BufReader::new(TcpStream::connect(addr).unwrap())),
+        };
+
+        reader.get_mut().write_all(request).unwrap();
+        match read_response(reader) {
+            Ok(close) => {
+                answered += 1;
+                if close || !keep_alive {
+                    connection = None;
+                }
+            }
+            Err(_) => connection = None,
+        }
+    }
+}
+
+// Reads one response and returns whether the server said it's
+// closing the connection.
+fn read_response(reader: &mut BufReader<TcpStream>) -> io::Result<bool> {
+    let mut length = 0;
+    let mut close = false;
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let header = line.trim_end().to_ascii_lowercase();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("content-length:") {
+            length = value.trim().parse().unwrap_or(0);
+        }
+        if header == "connection: close" {
+            close = true;
+        }
+    }
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(close)
+}
@@ -0,0 +1,59 @@
+// Sends requests saved with the capture_dir option to a running
+// server again, byte for byte, and prints the status line each one
+// gets back:
+//
+//   cargo run --example replay -- 127.0.0.1:8787 captures/*.http
+//
+// With --full the whole of each response is printed instead.
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::process;
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let full = args.iter().any(|arg| arg == "--full");
+    args.retain(|arg| arg != "--full");
+    if args.len() < 2 {
+        eprintln!("usage: replay [--full] <addr> <file>...");
+        process::exit(2);
+    }
+
+    let addr = &args[0];
+    let mut failed = false;
+    for file in &args[1..] {
+        match replay(addr, file) {
+            Ok(response) if full => {
+                println!("==> {} <==", file);
+                io::stdout().write_all(&response).unwrap();
+                println!();
+            }
+            Ok(response) => {
+                let status = response.split(|&b| b == b'\n').next().unwrap_or(&[]);
+                println!("{}: {}", file, String::from_utf8_lossy(status).trim_end());
+            }
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+// Sends the request in file to addr on a connection of its own and
+// returns everything the server sent back before closing it.
+fn replay(addr: &str, file: &str) -> io::Result<Vec<u8>> {
+    let request = fs::read(file)?;
+    let mut stream = TcpStream::connect(addr)?;
+    stream.write_all(&request)?;
+    // Nothing else is coming, so the server closes the connection
+    // once it's answered, rather than waiting for another request.
+    stream.shutdown(Shutdown::Write)?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    Ok(response)
+}
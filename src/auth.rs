@@ -0,0 +1,125 @@
+// Deciding who gets to see what. Paths listed in Config::protected
+// are only answered for requests their authenticator lets through;
+// everyone else gets a 401 saying how to log in.
+//
+// An authenticator is anything that implements Authenticator, so a
+// site can check tokens, look users up in a file or ask another
+// service, and register it with Server::authenticator(). The one
+// that comes built in is BasicAuth, HTTP Basic auth against a fixed
+// list of users from the config.
+use crate::request::Request;
+
+// What an authenticator made of a request.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    // Let it through. The identity, if there is one, is whoever the
+    // request turned out to be from, and handlers get it from
+    // Request::identity().
+    Authorized(Option<String>),
+    // Answer 401 instead.
+    Unauthorized,
+}
+
+pub trait Authenticator: Send + Sync {
+    // Looks at the request (usually its Authorization header) and
+    // says whether it can go ahead.
+    fn authenticate(&self, request: &Request) -> Outcome;
+
+    // The WWW-Authenticate header sent with a 401, which tells the
+    // client how to authenticate. A browser that sees a Basic one
+    // asks the user for a name and password.
+    fn challenge(&self) -> String {
+        String::from("Basic realm=\"restricted\"")
+    }
+}
+
+// HTTP Basic auth: the client sends
+//
+//   Authorization: Basic base64(username:password)
+//
+// and it's checked against users. The identity is the username.
+// Basic auth sends the password with every request as good as in
+// the clear, so it only belongs behind TLS (a proxy in front, say).
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicAuth {
+    // What it's called in Config::protected. Browsers also show it
+    // when asking for a password.
+    pub realm: String,
+    // (username, password) pairs.
+    pub users: Vec<(String, String)>,
+}
+
+impl BasicAuth {
+    // The username and password an Authorization header holds, if
+    // it's a Basic one that decodes properly.
+    pub fn credentials(request: &Request) -> Option<(String, String)> {
+        let header = request.header("Authorization")?;
+        let (scheme, encoded) = header.split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("Basic") {
+            return None;
+        }
+        let decoded = String::from_utf8(base64_decode(encoded.trim())?).ok()?;
+        let (user, password) = decoded.split_once(':')?;
+        Some((user.to_string(), password.to_string()))
+    }
+}
+
+impl Authenticator for BasicAuth {
+    fn authenticate(&self, request: &Request) -> Outcome {
+        let (user, password) = match BasicAuth::credentials(request) {
+            Some(credentials) => credentials,
+            None => return Outcome::Unauthorized,
+        };
+        // Every user is checked, and every byte of the password, so
+        // how long this takes doesn't give away how close a guess
+        // was.
+        let mut found = false;
+        for (name, expected) in &self.users {
+            found |= *name == user && same(expected.as_bytes(), password.as_bytes());
+        }
+        if found {
+            Outcome::Authorized(Some(user))
+        } else {
+            Outcome::Unauthorized
+        }
+    }
+
+    fn challenge(&self) -> String {
+        format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm)
+    }
+}
+
+// Compares a and b without stopping at the first difference.
+pub(crate) fn same(a: &[u8], b: &[u8]) -> bool {
+    let mut difference = a.len() ^ b.len();
+    for (i, byte) in a.iter().enumerate() {
+        difference |= (byte ^ b.get(i).copied().unwrap_or(0)) as usize;
+    }
+    difference == 0
+}
+
+// Decodes standard base64 (A-Z a-z 0-9 + /, with = padding at the
+// end). Returns None for anything else.
+fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    let input = input.trim_end_matches('=');
+    let mut decoded = Vec::with_capacity(input.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for byte in input.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        bits = (bits << 6) | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            decoded.push((bits >> count) as u8);
+        }
+    }
+    Some(decoded)
+}
@@ -0,0 +1,240 @@
+// Setting a server up from Rust rather than from a config file, for
+// a program that embeds it:
+//
+//   Server::builder()
+//       .addr("0.0.0.0:8080")
+//       .static_root("public")
+//       .threads(8)
+//       .route("GET", "/", handler)
+//       .build()?
+//       .run()
+//
+// or .bind() in place of .run() to learn the address before serving
+// (see Server::bind()).
+//
+// Options without a method of their own can be set by name with
+// option(), the same names the config file takes, or the whole
+// config swapped in with config() (from Config::load(), say) and the
+// rest built on top of it.
+//
+// Nothing is checked until build(), which turns down options that
+// contradict each other and the first value option() couldn't use.
+use std::path::PathBuf;
+
+use crate::auth::Authenticator;
+use crate::config::{self, Config, ConfigError};
+use crate::error::HttpError;
+use crate::events::Event;
+use crate::request::{BodyReader, Request};
+use crate::response::Response;
+use crate::server::Server;
+
+// What build() is told the problem was with, in ConfigError's
+// source.
+const SOURCE: &str = "builder";
+
+// Something to do to the server once build() has made it.
+type Registration = Box<dyn FnOnce(&mut Server)>;
+
+pub struct ServerBuilder {
+    config: Config,
+    // The first value option() couldn't use, given back by build().
+    error: Option<ConfigError>,
+    // Routes, hooks and the rest, registered on the server once
+    // there is one.
+    registrations: Vec<Registration>,
+    // The names authenticator() was given, so build() can tell
+    // whether everything in Config::protected has one.
+    authenticators: Vec<String>,
+}
+
+impl ServerBuilder {
+    // Starts from the defaults, the same ones Config::default() has.
+    pub fn new() -> ServerBuilder {
+        ServerBuilder {
+            config: Config::default(),
+            error: None,
+            registrations: Vec::new(),
+            authenticators: Vec::new(),
+        }
+    }
+
+    // Replaces every option with those in config. Anything set before
+    // this is lost, so call it first.
+    pub fn config(mut self, config: Config) -> ServerBuilder {
+        self.config = config;
+        self
+    }
+
+    // Sets the option called key, as a line key = value in a config
+    // file would.
+    pub fn option(mut self, key: &str, value: &str) -> ServerBuilder {
+        if let Err(e) = self.config.apply(SOURCE, key, value) {
+            self.error.get_or_insert(e);
+        }
+        self
+    }
+
+    pub fn addr(mut self, addr: &str) -> ServerBuilder {
+        self.config.addr = addr.to_string();
+        self
+    }
+
+    pub fn unix_socket<P: Into<PathBuf>>(mut self, path: P) -> ServerBuilder {
+        self.config.unix_socket = Some(path.into());
+        self
+    }
+
+    pub fn static_root<P: Into<PathBuf>>(mut self, path: P) -> ServerBuilder {
+        self.config.static_root.path = path.into();
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> ServerBuilder {
+        self.config.threads = threads;
+        self
+    }
+
+    // See Server::route().
+    pub fn route<F>(self, method: &str, path: &str, handler: F) -> ServerBuilder
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        let (method, path) = (method.to_string(), path.to_string());
+        self.register(move |server| server.route(&method, &path, handler))
+    }
+
+    // See Server::route_fallible().
+    pub fn route_fallible<F>(self, method: &str, path: &str, handler: F) -> ServerBuilder
+    where
+        F: Fn(&Request) -> Result<Response, HttpError> + Send + Sync + 'static,
+    {
+        let (method, path) = (method.to_string(), path.to_string());
+        self.register(move |server| server.route_fallible(&method, &path, handler))
+    }
+
+    // See Server::route_streaming().
+    pub fn route_streaming<F>(self, method: &str, path: &str, handler: F) -> ServerBuilder
+    where
+        F: Fn(&Request, &mut BodyReader) -> Response + Send + Sync + 'static,
+    {
+        let (method, path) = (method.to_string(), path.to_string());
+        self.register(move |server| server.route_streaming(&method, &path, handler))
+    }
+
+    // See Server::rewrite().
+    pub fn rewrite<F>(self, rewriter: F) -> ServerBuilder
+    where
+        F: Fn(&mut Request) -> Option<Response> + Send + Sync + 'static,
+    {
+        self.register(move |server| server.rewrite(rewriter))
+    }
+
+    // See Server::authenticator().
+    pub fn authenticator<A>(mut self, name: &str, authenticator: A) -> ServerBuilder
+    where
+        A: Authenticator + 'static,
+    {
+        self.authenticators.push(config::normalize(name));
+        let name = name.to_string();
+        self.register(move |server| server.authenticator(&name, authenticator))
+    }
+
+    // See Server::on_event().
+    pub fn on_event<F>(self, hook: F) -> ServerBuilder
+    where
+        F: Fn(&Event) + Send + Sync + 'static,
+    {
+        self.register(move |server| server.on_event(hook))
+    }
+
+    // See Server::reload_on_hangup().
+    pub fn reload_on_hangup<F>(self, loader: F) -> ServerBuilder
+    where
+        F: Fn() -> Result<Config, ConfigError> + Send + Sync + 'static,
+    {
+        self.register(move |server| server.reload_on_hangup(loader))
+    }
+
+    fn register<F>(mut self, registration: F) -> ServerBuilder
+    where
+        F: FnOnce(&mut Server) + 'static,
+    {
+        self.registrations.push(Box::new(registration));
+        self
+    }
+
+    // Checks the options make sense together and makes the server.
+    pub fn build(self) -> Result<Server, ConfigError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        check(&self.config, &self.authenticators).map_err(|message| ConfigError::Invalid {
+            source: String::from(SOURCE),
+            message,
+        })?;
+
+        let mut server = Server::new(self.config);
+        for registration in self.registrations {
+            registration(&mut server);
+        }
+        Ok(server)
+    }
+}
+
+impl Default for ServerBuilder {
+    fn default() -> ServerBuilder {
+        ServerBuilder::new()
+    }
+}
+
+// What's wrong with config, if anything. These are all options that
+// would be accepted one at a time but can't do what was meant
+// together, and would otherwise only show up once the server was
+// running, as a failure to start or as requests going wrong.
+fn check(config: &Config, authenticators: &[String]) -> Result<(), String> {
+    if config.addr.is_empty() && config.unix_socket.is_none() {
+        return Err(String::from("no addr or unix_socket to listen on"));
+    }
+    if config.threads == 0 {
+        return Err(String::from("threads must be at least 1"));
+    }
+    if let (Some(high), Some(low)) = (config.keep_alive_high_water, config.keep_alive_low_water) {
+        if low > high {
+            return Err(format!(
+                "keep_alive_low_water ({}) is above keep_alive_high_water ({})",
+                low, high
+            ));
+        }
+    }
+    if config.canonical_scheme.is_some() && config.canonical_host.is_none() {
+        return Err(String::from(
+            "canonical_scheme is set but canonical_host isn't, so nothing would be redirected",
+        ));
+    }
+    if config.https_redirect && config.canonical_scheme.as_deref() == Some("http") {
+        return Err(String::from(
+            "https_redirect is on but canonical_scheme is http, so they'd redirect each other's requests",
+        ));
+    }
+    let root = &config.static_root;
+    if let Some(language) = &root.default_language {
+        if !root.languages.contains(language) {
+            return Err(format!(
+                "default_language {:?} isn't one of languages",
+                language
+            ));
+        }
+    }
+    for (name, _) in &config.protected {
+        let known = authenticators.contains(name)
+            || config.basic_auth.iter().any(|auth| auth.realm == *name);
+        if !known {
+            return Err(format!(
+                "protected.{} has no authenticator: no basic_auth.{} and none registered with that name",
+                name, name
+            ));
+        }
+    }
+    Ok(())
+}
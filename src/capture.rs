@@ -0,0 +1,131 @@
+// Saving the bytes of each request exactly as they came in, for
+// Config::capture_dir. Each request goes in a file of its own, which
+// examples/replay.rs can send to a server again to reproduce a bug
+// or replay real traffic in a load test:
+//
+//   cargo run --example replay -- 127.0.0.1:8787 captures/*.http
+//
+// What's captured is whatever the client sent, passwords and cookies
+// included, so it's off unless asked for and the server warns about
+// it while it's on.
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::connection::{Closer, Connection, Peer};
+
+// Numbers the files, so two requests in the same millisecond don't
+// get the same name.
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+// A connection that remembers what's read from it, up to a limit, so
+// the bytes of each request can be saved once it's been answered.
+// With no limit it remembers nothing and just passes reads through.
+pub struct Recorder<C> {
+    inner: C,
+    limit: Option<usize>,
+    // The start of the current request, up to limit bytes.
+    seen: Vec<u8>,
+    // How many bytes of it there were in all, limit or not.
+    total: usize,
+}
+
+impl<C: Connection> Recorder<C> {
+    // Records up to limit bytes of each request, or nothing with None.
+    pub fn new(inner: C, limit: Option<usize>) -> Recorder<C> {
+        Recorder {
+            inner,
+            limit,
+            seen: Vec::new(),
+            total: 0,
+        }
+    }
+
+    // Starts on a new request. buffer is what's been read of it
+    // already, left over after the last one.
+    pub fn start(&mut self, buffer: &[u8]) {
+        self.seen.clear();
+        self.total = 0;
+        self.record(buffer);
+    }
+
+    // The bytes of the request since start(), and whether there were
+    // more than the limit. left is how many of the bytes read are
+    // still in the buffer, which belong to the next request.
+    pub fn finish(&mut self, left: usize) -> (Vec<u8>, bool) {
+        let len = self.total.saturating_sub(left);
+        let mut seen = std::mem::take(&mut self.seen);
+        seen.truncate(len);
+        let cut = len > seen.len();
+        (seen, cut)
+    }
+
+    fn record(&mut self, bytes: &[u8]) {
+        if let Some(limit) = self.limit {
+            let room = limit.saturating_sub(self.seen.len());
+            self.seen.extend_from_slice(&bytes[..bytes.len().min(room)]);
+            self.total += bytes.len();
+        }
+    }
+}
+
+impl<C: Connection> Read for Recorder<C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.record(&buf[..read]);
+        Ok(read)
+    }
+}
+
+impl<C: Connection> Write for Recorder<C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<C: Connection> Connection for Recorder<C> {
+    fn peer(&self) -> io::Result<Peer> {
+        self.inner.peer()
+    }
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_write_timeout(timeout)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.inner.set_nonblocking(nonblocking)
+    }
+    fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        self.inner.set_nodelay(nodelay)
+    }
+    fn send_file(&mut self, file: &File, offset: u64, len: u64) -> io::Result<()> {
+        self.inner.send_file(file, offset, len)
+    }
+    fn closer(&self) -> Option<Closer> {
+        self.inner.closer()
+    }
+}
+
+// Writes bytes to a new file in dir, named after when it was saved,
+// and returns its path.
+pub fn save(dir: &Path, bytes: &[u8]) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_millis())
+        .unwrap_or(0);
+    let path = dir.join(format!(
+        "{}-{}.http",
+        millis,
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut file = File::create(&path)?;
+    file.write_all(bytes)?;
+    Ok(path)
+}
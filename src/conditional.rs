@@ -0,0 +1,198 @@
+// Conditional requests: the If-* headers that let a client say "only
+// do this if the resource is (or isn't) the version I think it is".
+// Caches use them to revalidate (304 Not Modified) and writers use
+// them to avoid clobbering someone else's change (412 Precondition
+// Failed).
+use std::time::SystemTime;
+
+use crate::httpdate;
+use crate::request::Request;
+
+// What the preconditions say to do with the request.
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    // Carry on as if there were no conditions.
+    Proceed,
+    // The client's copy is current: answer 304 with no body.
+    NotModified,
+    // A condition failed: answer 412 and don't do anything.
+    Failed,
+}
+
+// The current state of the resource a request is aimed at. etag and
+// last_modified are None if the resource doesn't exist (or doesn't
+// have one).
+pub struct Resource<'a> {
+    pub exists: bool,
+    pub etag: Option<&'a str>,
+    pub last_modified: Option<SystemTime>,
+}
+
+// Checks the request's preconditions against resource, in the order
+// RFC 9110 section 13.2.2 lays out. The order matters because some
+// conditions are ignored when another is present:
+//
+//   1. If-Match, and only if that's absent, If-Unmodified-Since
+//   2. If-None-Match, and only if that's absent, If-Modified-Since
+//      (which only applies to GET and HEAD)
+//
+// Step 1 failing is always a 412. Step 2 "failing" is a 304 for GET
+// and HEAD, since the client already has what it would have got, and
+// a 412 for anything else.
+pub fn evaluate(request: &Request, resource: &Resource) -> Outcome {
+    let safe = request.method == "GET" || request.method == "HEAD";
+
+    if let Some(if_match) = request.header("If-Match") {
+        if !matches_any(if_match, resource, false) {
+            return Outcome::Failed;
+        }
+    } else if let Some(since) = request.header("If-Unmodified-Since") {
+        // Dates that don't parse are ignored, as the spec says.
+        if let (Some(since), Some(modified)) = (httpdate::parse(since), resource.last_modified) {
+            if httpdate::truncate(modified) > since {
+                return Outcome::Failed;
+            }
+        }
+    }
+
+    if let Some(if_none_match) = request.header("If-None-Match") {
+        if matches_any(if_none_match, resource, true) {
+            return if safe {
+                Outcome::NotModified
+            } else {
+                Outcome::Failed
+            };
+        }
+    } else if safe {
+        if let Some(since) = request.header("If-Modified-Since") {
+            if let (Some(since), Some(modified)) = (httpdate::parse(since), resource.last_modified)
+            {
+                if httpdate::truncate(modified) <= since {
+                    return Outcome::NotModified;
+                }
+            }
+        }
+    }
+
+    Outcome::Proceed
+}
+
+// Whether a header like If-Match takes: either * (any version, as
+// long as the resource exists) or a comma separated list of ETags.
+//
+// If-Match uses strong comparison, where a weak W/"..." ETag never
+// matches anything, because it's protecting a write and "roughly the
+// same" isn't good enough. If-None-Match uses weak comparison, which
+// ignores the W/ on both sides.
+fn matches_any(header: &str, resource: &Resource, weak: bool) -> bool {
+    if header.trim() == "*" {
+        return resource.exists;
+    }
+    let current = match resource.etag {
+        Some(etag) => etag,
+        None => return false,
+    };
+
+    header.split(',').map(str::trim).any(|candidate| {
+        if weak {
+            strip_weak(candidate) == strip_weak(current)
+        } else {
+            !candidate.starts_with("W/") && !current.starts_with("W/") && candidate == current
+        }
+    })
+}
+
+fn strip_weak(etag: &str) -> &str {
+    etag.strip_prefix("W/").unwrap_or(etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    const MODIFIED: &str = "Sun, 06 Nov 1994 08:49:37 GMT";
+    const EARLIER: &str = "Sat, 05 Nov 1994 08:49:37 GMT";
+
+    // A request with method and headers.
+    fn request(method: &str, headers: &[(&str, &str)]) -> Request {
+        let mut raw = format!("{} / HTTP/1.1\r\nHost: a\r\n", method);
+        for (name, value) in headers {
+            raw.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        raw.push_str("\r\n");
+        Request::parse(raw.as_bytes()).unwrap()
+    }
+
+    // The outcome for a resource with ETag "v2", last modified at
+    // MODIFIED.
+    fn evaluate_for(method: &str, headers: &[(&str, &str)]) -> Outcome {
+        let resource = Resource {
+            exists: true,
+            etag: Some("\"v2\""),
+            last_modified: Some(UNIX_EPOCH + Duration::from_secs(784111777)),
+        };
+        evaluate(&request(method, headers), &resource)
+    }
+
+    #[test]
+    fn if_match_wins_over_if_unmodified_since() {
+        let headers = [("If-Match", "\"v2\""), ("If-Unmodified-Since", EARLIER)];
+        assert_eq!(evaluate_for("PUT", &headers), Outcome::Proceed);
+        let headers = [("If-Match", "\"v1\""), ("If-Unmodified-Since", MODIFIED)];
+        assert_eq!(evaluate_for("PUT", &headers), Outcome::Failed);
+    }
+
+    #[test]
+    fn if_unmodified_since_fails_for_a_newer_resource() {
+        let headers = [("If-Unmodified-Since", EARLIER)];
+        assert_eq!(evaluate_for("PUT", &headers), Outcome::Failed);
+        let headers = [("If-Unmodified-Since", MODIFIED)];
+        assert_eq!(evaluate_for("PUT", &headers), Outcome::Proceed);
+    }
+
+    #[test]
+    fn if_match_is_strong() {
+        let headers = [("If-Match", "W/\"v2\"")];
+        assert_eq!(evaluate_for("PUT", &headers), Outcome::Failed);
+    }
+
+    #[test]
+    fn a_matching_if_none_match_is_a_304_for_get_and_a_412_otherwise() {
+        let headers = [("If-None-Match", "\"v1\", W/\"v2\"")];
+        assert_eq!(evaluate_for("GET", &headers), Outcome::NotModified);
+        assert_eq!(evaluate_for("HEAD", &headers), Outcome::NotModified);
+        assert_eq!(evaluate_for("PUT", &headers), Outcome::Failed);
+    }
+
+    #[test]
+    fn if_none_match_wins_over_if_modified_since() {
+        let headers = [("If-None-Match", "\"v1\""), ("If-Modified-Since", MODIFIED)];
+        assert_eq!(evaluate_for("GET", &headers), Outcome::Proceed);
+    }
+
+    #[test]
+    fn if_modified_since_is_a_304_for_an_unchanged_resource() {
+        let headers = [("If-Modified-Since", MODIFIED)];
+        assert_eq!(evaluate_for("GET", &headers), Outcome::NotModified);
+        let headers = [("If-Modified-Since", EARLIER)];
+        assert_eq!(evaluate_for("GET", &headers), Outcome::Proceed);
+        // Only GET and HEAD are answered from a date.
+        let headers = [("If-Modified-Since", MODIFIED)];
+        assert_eq!(evaluate_for("PUT", &headers), Outcome::Proceed);
+    }
+
+    #[test]
+    fn dates_that_dont_parse_are_ignored() {
+        let huge = "Thu, 01 Jan 300000000000 00:00:00 GMT";
+        assert_eq!(
+            evaluate_for("GET", &[("If-Modified-Since", huge)]),
+            Outcome::Proceed
+        );
+        assert_eq!(
+            evaluate_for("PUT", &[("If-Unmodified-Since", huge)]),
+            Outcome::Proceed
+        );
+    }
+}
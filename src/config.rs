@@ -0,0 +1,907 @@
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::auth::BasicAuth;
+use crate::log::Level;
+use crate::response;
+
+// What Config::security_headers adds: don't guess a content type
+// other than the one we send, only let pages from this site put ours
+// in a frame, and don't tell other sites more than our origin in
+// Referer.
+pub const SECURITY_HEADERS: [(&str, &str); 3] = [
+    ("X-Content-Type-Options", "nosniff"),
+    ("X-Frame-Options", "SAMEORIGIN"),
+    ("Referrer-Policy", "strict-origin-when-cross-origin"),
+];
+
+// Environment variables are the option name in upper case with
+// this in front, so threads is RUST_SERVER_THREADS.
+const ENV_PREFIX: &str = "RUST_SERVER_";
+
+// Config holds every knob the server can be tuned with. It's
+// a plain struct with public fields so it's easy to build one
+// by hand, and Default gives the values the server used back
+// when everything was hardcoded.
+#[derive(Debug, Clone)]
+pub struct Config {
+    // The address the listener binds to. Empty means no TCP
+    // listener, for a server that only uses unix_socket.
+    pub addr: String,
+    // A Unix domain socket to listen on as well as (or, with addr
+    // empty, instead of) TCP. Only works with the unix-socket
+    // feature.
+    pub unix_socket: Option<PathBuf>,
+    // Proxies whose X-Forwarded-For, X-Forwarded-Proto and
+    // X-Forwarded-Host headers are believed (see forwarded.rs). From
+    // any other peer they're ignored, since a client could put
+    // whatever it likes in them. A loopback address here trusts the
+    // Unix socket too. Empty, the default, trusts nobody.
+    pub trusted_proxies: Vec<IpAddr>,
+    // How many worker threads handle connections.
+    pub threads: usize,
+    // How many accepted connections can wait for a free worker.
+    // Once that many are waiting, new ones get a 503 straight away
+    // rather than queueing up without limit during a spike. It's the
+    // size of the worker pool's queue, so like threads it's only read
+    // when the server starts.
+    pub max_queued_connections: usize,
+    // How many connections one IP address can have open (or waiting
+    // for a worker) at once. Past that, its new connections get a
+    // 503 straight away, so one client can't take every worker for
+    // itself. trusted_proxies aren't limited, since all their clients
+    // share their address. None, the default, has no limit.
+    pub max_connections_per_ip: Option<usize>,
+    // The directory files are served out of.
+    pub static_root: StaticRoot,
+    // More directories to look in, in order, for anything that isn't
+    // in the static root: a site's own files can go in the static
+    // root and override a theme or a base site kept in a fallback.
+    // They share the static root's index_files, directory_listing and
+    // languages.
+    pub fallback_roots: Vec<PathBuf>,
+    // Dotfiles (.git, .env, ...) often hold things that should
+    // never leave the machine, so serving them is opt-in. When
+    // this is false any request with a path segment starting
+    // with a . gets a 403.
+    pub serve_dotfiles: bool,
+    // Symlinks under the root that lead outside it are refused with
+    // a 403, since otherwise one link (html/leak -> /etc) would
+    // expose whatever it points at. Sites that link outside the
+    // root on purpose can turn this on to follow them anyway.
+    // Symlinks that stay inside the root always work.
+    pub follow_symlinks: bool,
+    // A 403 says there's something at a path, just not for you. With
+    // this on, every path we refuse (a dotfile, a .. or a symlink out
+    // of the root, anything else that would be a 403) gets a 404
+    // instead, so nobody can tell it from one that isn't there.
+    pub hide_forbidden: bool,
+    // Build pipelines put a content hash in asset names
+    // (app.3f9a1c2e.js) so a changed file always gets a new name.
+    // Files whose name matches this glob are sent with a year-long
+    // immutable Cache-Control, since their contents can never
+    // change under that name. None turns the check off.
+    pub immutable_pattern: Option<String>,
+    // The Cache-Control sent with every other file. The default
+    // no-cache still lets browsers keep a copy, they just have
+    // to check with us before using it.
+    pub cache_control: String,
+    // Files with no extension, or one we don't know, are normally
+    // sent as application/octet-stream, which browsers download.
+    // With this on, their first bytes are looked at to tell HTML,
+    // plain text and a few image formats and PDF apart instead. A
+    // known extension always decides the type on its own. Leave it
+    // off if clients can upload files, or an upload without an
+    // extension could be served as a page running its own scripts.
+    pub sniff_content_type: bool,
+    // Sends a compressed copy of a file kept next to it
+    // (style.css.br or style.css.gz for style.css) to clients whose
+    // Accept-Encoding takes it, so big text files go out smaller
+    // without being compressed on every request. A copy older than
+    // the file it's of is left alone, since it was made before the
+    // last change and would send the old contents.
+    pub precompressed: bool,
+    // TRACE echoes the request back, which is handy for seeing what
+    // a proxy did to it but also lets a script read headers it
+    // shouldn't (cross-site tracing). Off means TRACE gets a 405.
+    pub allow_trace: bool,
+    // Lets a POST carry an X-HTTP-Method-Override header saying
+    // which method it really is, for clients (old browsers, some
+    // proxies) that can only send GET and POST. Off by default
+    // because it changes which handler a request reaches.
+    pub method_override: bool,
+    // Lets clients change files under the static root with PUT and
+    // DELETE. Anyone who can reach the server can then overwrite the
+    // site, so only turn it on behind something that checks who's
+    // asking.
+    pub allow_writes: bool,
+    // Lets DELETE remove empty directories too, not just files. A
+    // directory with anything in it is never removed.
+    pub delete_directories: bool,
+    // The longest request line we'll read before giving up with a
+    // 414, in bytes.
+    pub max_request_line: usize,
+    // The most bytes the request line and headers together can take
+    // up before we give up with a 431.
+    pub max_header_size: usize,
+    // How much is read from a connection at a time while reading a
+    // request head (or the lines of a chunked body), in bytes. A
+    // head that fits is read in one go; a bigger one takes a few
+    // reads. Each worker has one of these while it reads, so raising
+    // it costs memory times threads.
+    pub read_buffer_size: usize,
+    // How much of a response is gathered before it's written to the
+    // connection, in bytes. A response that fits goes out in a single
+    // write; a body bigger than this is written straight through. A
+    // larger buffer means fewer writes (and packets) for mid-sized
+    // responses, at the cost of that much memory for each response
+    // being sent at once.
+    pub write_buffer_size: usize,
+    // The biggest request body we'll hold in memory, in bytes.
+    // Anything bigger gets a 413 without being read. This is what
+    // caps an upload, separately from the headers above.
+    pub max_body_size: usize,
+    // The methods whose requests can have a body. A body sent with
+    // any other, like a GET with a Content-Length, is still read so
+    // the next request on the connection starts in the right place,
+    // and then thrown away, or refused with a 400 if
+    // reject_unexpected_body is on.
+    pub body_methods: Vec<String>,
+    pub reject_unexpected_body: bool,
+    // Globs for the paths whose handlers only take text in UTF-8,
+    // like /api/*. A body sent there as text (text/*, JSON, XML or a
+    // form) that isn't valid UTF-8 gets a 400 saying so, instead of
+    // reaching a handler that would quietly turn the bad bytes into
+    // replacement characters. Other types, like an image upload, and
+    // text that says it's in some other charset aren't checked.
+    // Empty, the default, checks nothing.
+    pub utf8_body_paths: Vec<String>,
+    // How long an idle keep-alive connection is held open waiting
+    // for its next request.
+    pub keep_alive_timeout: Duration,
+    // How many requests one connection can make before we close it,
+    // so a single client can't keep a worker to itself forever.
+    pub max_keep_alive_requests: usize,
+    // How long one connection can be kept open in all, however busy
+    // it is. The response to the first request after that closes it,
+    // so long-lived clients come back on a new connection now and
+    // then and pick up whatever's changed since. None, the default,
+    // leaves it to the limits above.
+    pub max_connection_age: Option<Duration>,
+    // How many pipelined requests (ones sent before the response to
+    // the one ahead of them) we'll answer in a row before closing the
+    // connection. The client has to send the rest again on a new
+    // one, which clients that pipeline are required to cope with.
+    pub max_pipelined_requests: usize,
+    // With more connections than this being answered at once, every
+    // response closes its connection instead of keeping it open, so
+    // idle keep-alive clients stop holding on to workers that others
+    // are waiting for. Keep-alive comes back once the count is down
+    // to keep_alive_low_water, or to the high water mark itself if
+    // that's not set. None, the default, never turns it off.
+    pub keep_alive_high_water: Option<usize>,
+    pub keep_alive_low_water: Option<usize>,
+    // The longest a request can take from its head arriving to its
+    // response going out. Handlers can see it as Request::deadline(),
+    // and a response that isn't out by then is abandoned and the
+    // connection closed, so a runaway handler can't hold a client
+    // forever.
+    pub max_request_duration: Duration,
+    // How long writing a response can be stuck before we give up on
+    // the client, so one that sends requests without ever reading
+    // the answers can't hold a worker forever.
+    pub write_timeout: Duration,
+    // How long a SIGTERM or Ctrl-C waits for the requests being
+    // answered to finish. Connections idling between requests are
+    // closed straight away; ones still busy after this are closed
+    // anyway, and the server exits.
+    pub drain_timeout: Duration,
+    // Requests that take longer than this to answer get a warning in
+    // the log on top of their access log line, to help find slow
+    // endpoints. None, the default, warns about none of them.
+    pub slow_request_threshold: Option<Duration>,
+    // For testing clients against a slow server: every response
+    // waits this long before going out. The wait counts against
+    // max_request_duration like a slow handler would. Never turn it
+    // on in production. None, the default, means no delay.
+    pub response_delay: Option<Duration>,
+    // Globs for the request paths response_delay applies to, like
+    // /api/*. Empty means every path.
+    pub response_delay_paths: Vec<String>,
+    // What to send in place of the plain text body for these error
+    // statuses, like a JSON body for an API's 404s.
+    pub error_pages: HashMap<u16, ErrorPage>,
+    // Maintenance mode: every request gets maintenance_page with a
+    // 503, except health_path, so a deploy can put up a "back soon"
+    // page without stopping the server.
+    pub maintenance: bool,
+    pub maintenance_page: PathBuf,
+    // Sent as Retry-After with the maintenance page, and when we're
+    // too busy to take a connection, to say how long clients should
+    // wait before trying again.
+    pub retry_after: Duration,
+    // The one host name the site should be reached on, like
+    // example.com. Requests for any other (www.example.com, the bare
+    // IP) are redirected to the same path on this one, so there's
+    // only ever one address for each page. health_path is left
+    // alone, since load balancers check that by IP. None, the
+    // default, answers on any host.
+    pub canonical_host: Option<String>,
+    // http or https, to redirect requests that came in on the other
+    // one as well. None keeps whichever the client used.
+    pub canonical_scheme: Option<String>,
+    // Sends every request that came in over plain http to the same
+    // path and query on https, on canonical_host if that's set and on
+    // the host the client asked for if it isn't. There's no TLS here,
+    // so this is for behind a proxy that takes care of it and says
+    // which a request used in X-Forwarded-Proto (see trusted_proxies).
+    // Off by default, for sites that do want to serve plain http.
+    pub https_redirect: bool,
+    // Users for HTTP Basic auth, as lists of name:password, each list
+    // an authenticator of its own:
+    //
+    //   basic_auth.admin = alice:secret, bob:hunter2
+    //
+    // A list on its own protects nothing; protected says where it's
+    // needed.
+    pub basic_auth: Vec<BasicAuth>,
+    // Which paths need logging in for, as globs by the name of the
+    // authenticator that checks them:
+    //
+    //   protected.admin = /admin/*, /stats
+    //
+    // The name is one of basic_auth's or one registered with
+    // Server::authenticator(). Where a path matches more than one,
+    // the first in the config wins.
+    pub protected: Vec<(String, Vec<String>)>,
+    // Gives route handlers a session, state kept here on the server
+    // for each browser and tied to it with a cookie. See
+    // Request::session().
+    pub sessions: bool,
+    // How long a session lasts without a request before it's
+    // forgotten.
+    pub session_timeout: Duration,
+    // The cookie the session ID goes in.
+    pub session_cookie: String,
+    // Refuses requests to handlers that could change something
+    // (POST, PUT, DELETE and the like) with a 403 unless they carry
+    // the session's CSRF token, so another site can't submit forms
+    // here in a visitor's name. See csrf.rs. It needs a session to
+    // keep the token in, so it gives handlers one even if sessions
+    // is off.
+    pub csrf_protection: bool,
+    // A path that always answers 200 "ok", even in maintenance mode,
+    // for load balancers to check the server is up. A handler
+    // registered for it answers instead. None means there isn't one.
+    pub health_path: Option<String>,
+    // A path that answers a POST with the request it got: the request
+    // line, the headers (apart from credentials, as with TRACE) and
+    // the body, for checking what a client really sends. For
+    // debugging only. None, the default, means there isn't one.
+    pub echo_path: Option<String>,
+    // A path that answers a GET with the routing table: every route
+    // and what answers it, a handler, the static files, a redirect
+    // and so on, for checking a config does what was meant. It lists
+    // paths and methods, never handlers' insides or file system
+    // paths. None, the default, means there isn't one.
+    pub routes_path: Option<String>,
+    // A directory to save the bytes of every request to, one file
+    // each, exactly as they came in, for replaying later with
+    // examples/replay.rs. Only the first capture_max_bytes of a
+    // request are kept. It saves passwords, cookies and everything
+    // else clients send, so it's for debugging only. None, the
+    // default, saves nothing.
+    pub capture_dir: Option<PathBuf>,
+    pub capture_max_bytes: usize,
+    // Paths for the Allow and Disallow lines of a /robots.txt made up
+    // for sites without one of their own. See robots.rs.
+    pub robots_allow: Vec<String>,
+    pub robots_disallow: Vec<String>,
+    // The pages for a made up /sitemap.xml, as paths on this site or
+    // whole URLs. robots.txt points to it when there are some.
+    pub sitemap: Vec<String>,
+    // How much goes to stderr: error, warn, info (a line for every
+    // response) or debug (every detail of every connection).
+    pub log_level: Level,
+    // Headers added to every response, like X-Content-Type-Options.
+    // A response that already has one of these (because its handler
+    // set it) keeps its own value.
+    pub headers: Vec<(String, String)>,
+    // Adds the usual security headers to every response, as
+    // SECURITY_HEADERS lists them. Either a handler or headers can
+    // still set any of them differently.
+    pub security_headers: bool,
+    // A Content-Security-Policy for every response, saying where the
+    // page may load scripts, styles and so on from, like
+    // default-src 'self'. What a site needs depends too much on the
+    // site for there to be a default, so None sends none.
+    pub content_security_policy: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            addr: String::from("127.0.0.1:7878"),
+            unix_socket: None,
+            trusted_proxies: Vec::new(),
+            threads: 4,
+            max_queued_connections: 1024,
+            max_connections_per_ip: None,
+            static_root: StaticRoot::new("html"),
+            fallback_roots: Vec::new(),
+            serve_dotfiles: false,
+            follow_symlinks: false,
+            hide_forbidden: false,
+            immutable_pattern: None,
+            cache_control: String::from("no-cache"),
+            sniff_content_type: false,
+            precompressed: false,
+            allow_trace: false,
+            method_override: false,
+            allow_writes: false,
+            delete_directories: false,
+            max_request_line: 8 * 1024,
+            max_header_size: 16 * 1024,
+            read_buffer_size: 4 * 1024,
+            write_buffer_size: response::WRITE_BUFFER,
+            max_body_size: 10 * 1024 * 1024,
+            body_methods: ["POST", "PUT", "PATCH", "DELETE"]
+                .iter()
+                .map(|method| method.to_string())
+                .collect(),
+            reject_unexpected_body: false,
+            utf8_body_paths: Vec::new(),
+            keep_alive_timeout: Duration::from_secs(5),
+            max_keep_alive_requests: 100,
+            max_connection_age: None,
+            max_pipelined_requests: 16,
+            keep_alive_high_water: None,
+            keep_alive_low_water: None,
+            max_request_duration: Duration::from_secs(30),
+            write_timeout: Duration::from_secs(30),
+            drain_timeout: Duration::from_secs(30),
+            slow_request_threshold: None,
+            response_delay: None,
+            response_delay_paths: Vec::new(),
+            error_pages: vec![(404, ErrorPage::file("html/404.html"))]
+                .into_iter()
+                .collect(),
+            maintenance: false,
+            maintenance_page: PathBuf::from("html/maintenance.html"),
+            retry_after: Duration::from_secs(120),
+            canonical_host: None,
+            canonical_scheme: None,
+            https_redirect: false,
+            basic_auth: Vec::new(),
+            protected: Vec::new(),
+            sessions: false,
+            session_timeout: Duration::from_secs(30 * 60),
+            session_cookie: String::from("session"),
+            csrf_protection: false,
+            health_path: None,
+            echo_path: None,
+            routes_path: None,
+            capture_dir: None,
+            capture_max_bytes: 64 * 1024,
+            robots_allow: Vec::new(),
+            robots_disallow: Vec::new(),
+            sitemap: Vec::new(),
+            log_level: Level::Warn,
+            headers: Vec::new(),
+            security_headers: false,
+            content_security_policy: None,
+        }
+    }
+}
+
+impl Config {
+    // Builds the config the binary runs with. Each layer overrides
+    // the one before it:
+    //
+    //   1. the defaults
+    //   2. the config file, if --config or RUST_SERVER_CONFIG names one
+    //   3. RUST_SERVER_* environment variables
+    //   4. command line flags
+    pub fn load() -> Result<Config, ConfigError> {
+        Config::load_from(env::args().skip(1).collect(), env::vars().collect())
+    }
+
+    // load() with the arguments and environment passed in rather
+    // than read from the process.
+    pub fn load_from(
+        args: Vec<String>,
+        vars: Vec<(String, String)>,
+    ) -> Result<Config, ConfigError> {
+        let args = parse_args(&args)?;
+        let vars: Vec<(String, String)> = vars
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+            .collect();
+
+        let file = args
+            .iter()
+            .find(|(flag, _)| normalize(flag) == "config")
+            .map(|(_, value)| value.clone())
+            .or_else(|| {
+                vars.iter()
+                    .find(|(name, _)| name == &format!("{}CONFIG", ENV_PREFIX))
+                    .map(|(_, value)| value.clone())
+            });
+
+        let mut config = Config::default();
+        if let Some(file) = file {
+            config.apply_file(Path::new(&file))?;
+        }
+        for (name, value) in &vars {
+            let key = &name[ENV_PREFIX.len()..];
+            if normalize(key) != "config" {
+                config.apply(name, key, value)?;
+            }
+        }
+        for (flag, value) in &args {
+            if normalize(flag) != "config" {
+                config.apply(&format!("--{}", flag), flag, value)?;
+            }
+        }
+
+        Ok(config)
+    }
+
+    // Reads a config file. Each line is key = value, blank lines and
+    // lines starting with # are skipped, and the keys are the same
+    // option names the flags and environment variables use.
+    pub fn apply_file(&mut self, path: &Path) -> Result<(), ConfigError> {
+        let text = fs::read_to_string(path).map_err(|error| ConfigError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let source = format!("{} line {}", path.display(), number + 1);
+            let (key, value) = match line.find('=') {
+                Some(i) => (line[..i].trim(), line[i + 1..].trim()),
+                None => return Err(ConfigError::Syntax { source }),
+            };
+            // Quotes are optional, but let values keep leading or
+            // trailing spaces if they need them.
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+            self.apply(&source, key, value)?;
+        }
+
+        Ok(())
+    }
+
+    // Sets the option called key, saying source was where the value
+    // came from if it's no good.
+    pub(crate) fn apply(
+        &mut self,
+        source: &str,
+        key: &str,
+        value: &str,
+    ) -> Result<(), ConfigError> {
+        self.set(key, value).map_err(|message| match message {
+            None => ConfigError::UnknownKey {
+                source: source.to_string(),
+                key: key.to_string(),
+            },
+            Some(message) => ConfigError::Invalid {
+                source: source.to_string(),
+                message,
+            },
+        })
+    }
+
+    // Sets one option from its text form. This is the one list of
+    // option names, shared by the config file, the environment and
+    // the command line. Names are matched ignoring case, and -, _
+    // and . are all the same, so max-header-size, MAX_HEADER_SIZE and
+    // max_header_size all work.
+    //
+    // Fails with None if there's no option called key, or with a
+    // message saying what's wrong with the value.
+    fn set(&mut self, key: &str, value: &str) -> Result<(), Option<String>> {
+        // header.X-Frame-Options = DENY. The header name keeps its
+        // case, and since environment variable names can't have a -
+        // in them, _ stands in for it there.
+        if let (Some(prefix), Some(name)) = (key.get(..7), key.get(7..)) {
+            if normalize(prefix) == "header_" && !name.is_empty() {
+                let name = name.replace('_', "-");
+                self.headers
+                    .retain(|(existing, _)| !existing.eq_ignore_ascii_case(&name));
+                self.headers.push((name, value.to_string()));
+                return Ok(());
+            }
+        }
+
+        let key = normalize(key);
+
+        // error_page.<status> is the file, error_page_type.<status>
+        // and error_page_status.<status> what it's sent as. The last
+        // has to be checked first, since it starts with the first.
+        let error_page = |status: &str| -> Result<u16, Option<String>> {
+            parse(status).map_err(|e| Some(format!("bad status in {}: {}", key, e)))
+        };
+        if let Some(status) = key.strip_prefix("error_page_type_") {
+            let page = self.error_pages.entry(error_page(status)?).or_default();
+            page.content_type = parse_optional(value);
+            return Ok(());
+        }
+        if let Some(status) = key.strip_prefix("error_page_status_") {
+            let page = self.error_pages.entry(error_page(status)?).or_default();
+            page.status = match parse_optional(value) {
+                Some(value) => Some(parse(&value).map_err(Some)?),
+                None => None,
+            };
+            return Ok(());
+        }
+        if let Some(status) = key.strip_prefix("error_page_") {
+            let page = self.error_pages.entry(error_page(status)?).or_default();
+            page.file = parse_optional(value).map(PathBuf::from);
+            return Ok(());
+        }
+
+        if let Some(name) = key.strip_prefix("basic_auth_") {
+            let mut users = Vec::new();
+            for user in parse_list(value) {
+                match user.split_once(':') {
+                    Some((user, password)) => users.push((user.to_string(), password.to_string())),
+                    None => return Err(Some(format!("{:?} isn't name:password", user))),
+                }
+            }
+            self.basic_auth.retain(|auth| auth.realm != name);
+            self.basic_auth.push(BasicAuth {
+                realm: name.to_string(),
+                users,
+            });
+            return Ok(());
+        }
+        if let Some(name) = key.strip_prefix("protected_") {
+            self.protected.retain(|(existing, _)| existing != name);
+            let patterns = parse_list(value);
+            if !patterns.is_empty() {
+                self.protected.push((name.to_string(), patterns));
+            }
+            return Ok(());
+        }
+
+        match key.as_str() {
+            "addr" => self.addr = value.to_string(),
+            "unix_socket" => self.unix_socket = parse_optional(value).map(PathBuf::from),
+            "trusted_proxies" => {
+                self.trusted_proxies = parse_list(value)
+                    .iter()
+                    .map(|addr| {
+                        addr.parse()
+                            .map_err(|_| Some(format!("{:?} isn't an IP address", addr)))
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+            "port" => {
+                let port: u16 = parse(value).map_err(Some)?;
+                let host = match self.addr.rfind(':') {
+                    Some(i) => &self.addr[..i],
+                    None => &self.addr,
+                };
+                self.addr = format!("{}:{}", host, port);
+            }
+            "threads" => {
+                self.threads = parse(value).map_err(Some)?;
+                if self.threads == 0 {
+                    return Err(Some(String::from("threads must be at least 1")));
+                }
+            }
+            "max_queued_connections" => self.max_queued_connections = parse(value).map_err(Some)?,
+            "max_connections_per_ip" => {
+                self.max_connections_per_ip = match parse_optional(value) {
+                    Some(value) => Some(parse(&value).map_err(Some)?),
+                    None => None,
+                }
+            }
+            "root" | "static_root" => self.static_root.path = PathBuf::from(value),
+            "fallback_roots" => {
+                self.fallback_roots = parse_list(value).into_iter().map(PathBuf::from).collect()
+            }
+            "index_files" => self.static_root.index_files = parse_list(value),
+            "directory_listing" => self.static_root.directory_listing = parse_bool(value)?,
+            "languages" => self.static_root.languages = parse_list(value),
+            "default_language" => self.static_root.default_language = parse_optional(value),
+            "serve_dotfiles" => self.serve_dotfiles = parse_bool(value)?,
+            "follow_symlinks" => self.follow_symlinks = parse_bool(value)?,
+            "hide_forbidden" => self.hide_forbidden = parse_bool(value)?,
+            "immutable_pattern" => self.immutable_pattern = parse_optional(value),
+            "cache_control" => self.cache_control = value.to_string(),
+            "precompressed" => self.precompressed = parse_bool(value)?,
+            "sniff_content_type" => self.sniff_content_type = parse_bool(value)?,
+            "allow_trace" => self.allow_trace = parse_bool(value)?,
+            "method_override" => self.method_override = parse_bool(value)?,
+            "allow_writes" => self.allow_writes = parse_bool(value)?,
+            "delete_directories" => self.delete_directories = parse_bool(value)?,
+            "max_request_line" => self.max_request_line = parse(value).map_err(Some)?,
+            "max_header_size" => self.max_header_size = parse(value).map_err(Some)?,
+            "read_buffer_size" => {
+                self.read_buffer_size = parse(value).map_err(Some)?;
+                if self.read_buffer_size == 0 {
+                    return Err(Some(String::from("read_buffer_size must be at least 1")));
+                }
+            }
+            "write_buffer_size" => self.write_buffer_size = parse(value).map_err(Some)?,
+            "max_body_size" => self.max_body_size = parse(value).map_err(Some)?,
+            "body_methods" => self.body_methods = parse_list(value),
+            "reject_unexpected_body" => self.reject_unexpected_body = parse_bool(value)?,
+            "utf8_body_paths" => self.utf8_body_paths = parse_list(value),
+            "keep_alive_timeout" => self.keep_alive_timeout = parse_seconds(value)?,
+            "max_keep_alive_requests" => {
+                self.max_keep_alive_requests = parse(value).map_err(Some)?
+            }
+            "max_pipelined_requests" => self.max_pipelined_requests = parse(value).map_err(Some)?,
+            "keep_alive_high_water" => {
+                self.keep_alive_high_water = match parse_optional(value) {
+                    Some(value) => Some(parse(&value).map_err(Some)?),
+                    None => None,
+                }
+            }
+            "keep_alive_low_water" => {
+                self.keep_alive_low_water = match parse_optional(value) {
+                    Some(value) => Some(parse(&value).map_err(Some)?),
+                    None => None,
+                }
+            }
+            "max_connection_age" => {
+                self.max_connection_age = match parse_optional(value) {
+                    Some(value) => Some(parse_seconds(&value)?),
+                    None => None,
+                }
+            }
+            "max_request_duration" => self.max_request_duration = parse_seconds(value)?,
+            "write_timeout" => self.write_timeout = parse_seconds(value)?,
+            "drain_timeout" => self.drain_timeout = parse_seconds(value)?,
+            "slow_request_threshold" => {
+                self.slow_request_threshold = match parse_optional(value) {
+                    Some(value) => Some(parse_seconds(&value)?),
+                    None => None,
+                }
+            }
+            "response_delay" => {
+                self.response_delay = match parse_optional(value) {
+                    Some(value) => Some(parse_seconds(&value)?),
+                    None => None,
+                }
+            }
+            "response_delay_paths" => self.response_delay_paths = parse_list(value),
+            "maintenance" => self.maintenance = parse_bool(value)?,
+            "maintenance_page" => self.maintenance_page = PathBuf::from(value),
+            "retry_after" => self.retry_after = parse_seconds(value)?,
+            "canonical_host" => self.canonical_host = parse_optional(value),
+            "canonical_scheme" => {
+                self.canonical_scheme =
+                    parse_optional(value).map(|scheme| scheme.to_ascii_lowercase());
+                if let Some(scheme) = &self.canonical_scheme {
+                    if scheme != "http" && scheme != "https" {
+                        return Err(Some(format!("{:?} isn't http or https", value)));
+                    }
+                }
+            }
+            "https_redirect" => self.https_redirect = parse_bool(value)?,
+            "sessions" => self.sessions = parse_bool(value)?,
+            "session_timeout" => self.session_timeout = parse_seconds(value)?,
+            "session_cookie" => self.session_cookie = value.trim().to_string(),
+            "csrf_protection" => self.csrf_protection = parse_bool(value)?,
+            "health_path" => self.health_path = parse_optional(value),
+            "echo_path" => self.echo_path = parse_optional(value),
+            "routes_path" => self.routes_path = parse_optional(value),
+            "capture_dir" => self.capture_dir = parse_optional(value).map(PathBuf::from),
+            "capture_max_bytes" => self.capture_max_bytes = parse(value).map_err(Some)?,
+            "robots_allow" => self.robots_allow = parse_list(value),
+            "robots_disallow" => self.robots_disallow = parse_list(value),
+            "sitemap" => self.sitemap = parse_list(value),
+            "security_headers" => self.security_headers = parse_bool(value)?,
+            "content_security_policy" => self.content_security_policy = parse_optional(value),
+            "log_level" => {
+                self.log_level = Level::from_name(value).ok_or_else(|| {
+                    Some(format!(
+                        "{:?} isn't one of error, warn, info or debug",
+                        value
+                    ))
+                })?
+            }
+            _ => return Err(None),
+        }
+
+        Ok(())
+    }
+}
+
+// Splits command line arguments into (flag, value) pairs. Both
+// --port 8080 and --port=8080 work, and a flag with no value after
+// it (--serve-dotfiles) means true.
+fn parse_args(args: &[String]) -> Result<Vec<(String, String)>, ConfigError> {
+    let mut pairs = Vec::new();
+    let mut args = args.iter().peekable();
+
+    while let Some(arg) = args.next() {
+        let flag = match arg.strip_prefix("--") {
+            Some(flag) => flag,
+            None => {
+                return Err(ConfigError::Syntax {
+                    source: format!("argument {}", arg),
+                })
+            }
+        };
+
+        if let Some(i) = flag.find('=') {
+            pairs.push((flag[..i].to_string(), flag[i + 1..].to_string()));
+        } else if args.peek().is_some_and(|next| !next.starts_with("--")) {
+            pairs.push((flag.to_string(), args.next().unwrap().clone()));
+        } else {
+            pairs.push((flag.to_string(), String::from("true")));
+        }
+    }
+
+    Ok(pairs)
+}
+
+pub(crate) fn normalize(key: &str) -> String {
+    key.to_ascii_lowercase().replace(['-', '.'], "_")
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T, String>
+where
+    T::Err: fmt::Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|e| format!("{:?} isn't a valid number: {}", value, e))
+}
+
+fn parse_bool(value: &str) -> Result<bool, Option<String>> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err(Some(format!("{:?} isn't true or false", value))),
+    }
+}
+
+// A duration in seconds, fractions allowed.
+fn parse_seconds(value: &str) -> Result<Duration, Option<String>> {
+    let seconds: f64 = parse(value).map_err(Some)?;
+    if !seconds.is_finite() || seconds <= 0.0 {
+        return Err(Some(format!(
+            "{:?} isn't a positive number of seconds",
+            value
+        )));
+    }
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+// A comma separated list. An empty value is an empty list.
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+// An empty value switches an optional setting off.
+fn parse_optional(value: &str) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+// Why the config couldn't be loaded. source is always where the bad
+// value came from: a file and line, an environment variable, or a flag.
+#[derive(Debug)]
+pub enum ConfigError {
+    // The config file couldn't be read.
+    Io { path: PathBuf, error: io::Error },
+    // A line or argument isn't in a form we understand.
+    Syntax { source: String },
+    // There's no option with that name.
+    UnknownKey { source: String, key: String },
+    // The option exists but the value doesn't make sense for it.
+    Invalid { source: String, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, error } => {
+                write!(f, "couldn't read config file {}: {}", path.display(), error)
+            }
+            ConfigError::Syntax { source } => {
+                write!(f, "{}: expected key = value or --flag value", source)
+            }
+            ConfigError::UnknownKey { source, key } => {
+                write!(f, "{}: unknown option {:?}", source, key)
+            }
+            ConfigError::Invalid { source, message } => write!(f, "{}: {}", source, message),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+// What's sent for an error status. Anything left as None is what
+// it'd be without the page: the plain text message, the status
+// itself, and the content type that goes with the file's extension.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorPage {
+    pub file: Option<PathBuf>,
+    pub content_type: Option<String>,
+    // Sent instead of the error's own status, say 200 for a page
+    // that should look like it worked.
+    pub status: Option<u16>,
+}
+
+impl ErrorPage {
+    // The contents of file, sent with the error's status.
+    pub fn file<P: Into<PathBuf>>(file: P) -> ErrorPage {
+        ErrorPage {
+            file: Some(file.into()),
+            ..ErrorPage::default()
+        }
+    }
+}
+
+// A directory we serve files from, and how requests for the
+// directories inside it are answered.
+#[derive(Debug, Clone)]
+pub struct StaticRoot {
+    pub path: PathBuf,
+    // When a directory is requested, these are tried in order and
+    // the first one that exists is served.
+    pub index_files: Vec<String>,
+    // If none of the index files exist, list what's in the
+    // directory instead of sending a 404.
+    pub directory_listing: bool,
+    // The languages the site's pages are translated into, like en,
+    // fr and pt-BR. A translation sits next to the file it's of with
+    // the language before the extension (index.fr.html for
+    // index.html), and a request for the file gets whichever
+    // translation its Accept-Language likes best. Empty, the
+    // default, serves every file as it is.
+    pub languages: Vec<String>,
+    // The translation to send when the client accepts none of the
+    // ones there are. None sends the file itself.
+    pub default_language: Option<String>,
+}
+
+impl StaticRoot {
+    // A root at path with the usual index.html and no listings.
+    pub fn new<P: Into<PathBuf>>(path: P) -> StaticRoot {
+        StaticRoot {
+            path: path.into(),
+            index_files: vec![String::from("index.html")],
+            directory_listing: false,
+            languages: Vec::new(),
+            default_language: None,
+        }
+    }
+}
@@ -0,0 +1,239 @@
+// The kinds of connection the server can answer on. Everything past
+// accepting a connection only needs to read, write and set a few
+// socket options, so that's all Connection asks for, and TCP and
+// Unix sockets are handled by the same code.
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Cursor, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::time::Duration;
+
+use crate::response;
+
+// Who's on the other end of a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Peer {
+    Tcp(SocketAddr),
+    // Unix socket clients don't have an address of their own. The
+    // real client is usually whoever connected to the proxy in front.
+    Unix,
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Peer::Tcp(addr) => write!(f, "{}", addr),
+            Peer::Unix => write!(f, "unix"),
+        }
+    }
+}
+
+// Closes a connection from another thread, which wakes up a worker
+// blocked reading or writing it. See Connection::closer().
+pub type Closer = Box<dyn Fn() + Send>;
+
+// What handle_connnection() needs from a stream on top of reading and
+// writing it.
+pub trait Connection: Read + Write {
+    fn peer(&self) -> io::Result<Peer>;
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+    // Turns off Nagle's algorithm, for connections that have it.
+    fn set_nodelay(&self, _nodelay: bool) -> io::Result<()> {
+        Ok(())
+    }
+    // Writes len bytes of file, starting at offset. By default they're
+    // read into a buffer and written from there, which works for
+    // anything; sockets can do it in the kernel instead.
+    fn send_file(&mut self, file: &File, offset: u64, len: u64) -> io::Result<()> {
+        response::copy_file(file, offset, len, self)
+    }
+    // Something that shuts the connection down when called, for the
+    // server to close it while it's shutting down. None for
+    // connections that can't be, which are left to finish.
+    fn closer(&self) -> Option<Closer> {
+        None
+    }
+}
+
+impl Connection for TcpStream {
+    fn peer(&self) -> io::Result<Peer> {
+        self.peer_addr().map(Peer::Tcp)
+    }
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+    fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        TcpStream::set_nodelay(self, nodelay)
+    }
+    fn closer(&self) -> Option<Closer> {
+        let stream = self.try_clone().ok()?;
+        Some(Box::new(move || {
+            let _ = stream.shutdown(Shutdown::Both);
+        }))
+    }
+    #[cfg(all(target_os = "linux", target_pointer_width = "64", feature = "sendfile"))]
+    fn send_file(&mut self, file: &File, offset: u64, len: u64) -> io::Result<()> {
+        sendfile::send(self, file, offset, len)
+    }
+}
+
+#[cfg(all(unix, feature = "unix-socket"))]
+impl Connection for std::os::unix::net::UnixStream {
+    fn peer(&self) -> io::Result<Peer> {
+        Ok(Peer::Unix)
+    }
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_read_timeout(self, timeout)
+    }
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_write_timeout(self, timeout)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_nonblocking(self, nonblocking)
+    }
+    fn closer(&self) -> Option<Closer> {
+        let stream = self.try_clone().ok()?;
+        Some(Box::new(move || {
+            let _ = stream.shutdown(Shutdown::Both);
+        }))
+    }
+    #[cfg(all(target_os = "linux", target_pointer_width = "64", feature = "sendfile"))]
+    fn send_file(&mut self, file: &File, offset: u64, len: u64) -> io::Result<()> {
+        sendfile::send(self, file, offset, len)
+    }
+}
+
+// A connection that's just memory: reads come from the bytes it was
+// made with and writes pile up in output. It's for driving the
+// server without a socket, say from a test:
+//
+//   let mut connection = MemoryConnection::new(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n");
+//   handle_connnection(&mut connection, Peer::Unix, &server);
+//   // connection.output now holds the response
+//
+// Reading past the end gets an end of file, which the server treats
+// as the client hanging up. Server::dispatch() does all of this in
+// one call.
+#[derive(Debug, Default)]
+pub struct MemoryConnection {
+    input: Cursor<Vec<u8>>,
+    pub output: Vec<u8>,
+}
+
+impl MemoryConnection {
+    pub fn new(input: &[u8]) -> MemoryConnection {
+        MemoryConnection {
+            input: Cursor::new(input.to_vec()),
+            output: Vec::new(),
+        }
+    }
+}
+
+impl Read for MemoryConnection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl Write for MemoryConnection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// There's no socket, so there are no options to set.
+impl Connection for MemoryConnection {
+    fn peer(&self) -> io::Result<Peer> {
+        Ok(Peer::Unix)
+    }
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+    fn set_nonblocking(&self, _nonblocking: bool) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// So a connection can be lent to handle_connnection() and looked at
+// afterwards.
+impl<C: Connection> Connection for &mut C {
+    fn peer(&self) -> io::Result<Peer> {
+        (**self).peer()
+    }
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_write_timeout(timeout)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        (**self).set_nonblocking(nonblocking)
+    }
+    fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        (**self).set_nodelay(nodelay)
+    }
+    fn send_file(&mut self, file: &File, offset: u64, len: u64) -> io::Result<()> {
+        (**self).send_file(file, offset, len)
+    }
+    fn closer(&self) -> Option<Closer> {
+        (**self).closer()
+    }
+}
+
+// sendfile() copies from a file to a socket inside the kernel, so the
+// bytes never pass through our memory at all. That's a lot less work
+// for a big file than reading it in and writing it out again. It's
+// only used on 64 bit Linux, which has it in this form, and with the
+// sendfile feature; everywhere else files go through send_file()'s
+// usual copy.
+#[cfg(all(target_os = "linux", target_pointer_width = "64", feature = "sendfile"))]
+mod sendfile {
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::{AsRawFd, RawFd};
+
+    extern "C" {
+        fn sendfile(out_fd: RawFd, in_fd: RawFd, offset: *mut i64, count: usize) -> isize;
+    }
+
+    // Linux sends at most this much per call, whatever we ask for.
+    const MAX_CHUNK: u64 = 0x7fff_f000;
+
+    pub fn send<S: AsRawFd>(socket: &S, file: &File, offset: u64, len: u64) -> io::Result<()> {
+        let mut offset = offset as i64;
+        let mut left = len;
+        while left > 0 {
+            let count = left.min(MAX_CHUNK) as usize;
+            // offset is moved along by the kernel, and the file's own
+            // position is left alone.
+            let sent =
+                unsafe { sendfile(socket.as_raw_fd(), file.as_raw_fd(), &mut offset, count) };
+            match sent {
+                // The file got shorter after we said how long it was.
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                sent if sent < 0 => {
+                    let error = io::Error::last_os_error();
+                    if error.kind() != io::ErrorKind::Interrupted {
+                        return Err(error);
+                    }
+                }
+                sent => left -= sent as u64,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+// Cookies: the client sends back the ones it has for us in a Cookie
+// header,
+//
+//   Cookie: session=3f9a1c2e; theme="dark"
+//
+// and a response hands out new ones with a Set-Cookie header each,
+//
+//   Set-Cookie: session=3f9a1c2e; Path=/; HttpOnly; SameSite=Lax
+//
+// Set-Cookie is the one header that can't be folded into a single
+// comma separated line (dates in Expires have commas in them), so
+// every cookie gets a header of its own.
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+// The cookies in the Cookie headers among headers, by name. Parsing
+// is forgiving, since browsers and scripts aren't always careful:
+// space around the ; and = is ignored, a value in double quotes
+// loses them, and a pair with no = is skipped. If a name comes up
+// twice the first wins, which is the one for the most specific path.
+pub fn parse(headers: &[(String, String)]) -> HashMap<String, String> {
+    let mut cookies = HashMap::new();
+    let headers = headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Cookie"));
+    for (_, header) in headers {
+        for pair in header.split(';') {
+            let (name, value) = match pair.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => continue,
+            };
+            if name.is_empty() {
+                continue;
+            }
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+            cookies
+                .entry(name.to_string())
+                .or_insert_with(|| value.to_string());
+        }
+    }
+    cookies
+}
+
+// Whether a cookie goes along with requests that come from other
+// sites, like following a link from one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    // Never.
+    Strict,
+    // Only for top-level navigation, like following a link. Browsers
+    // treat cookies without a SameSite this way.
+    Lax,
+    // Always. Browsers insist on Secure along with it.
+    None,
+}
+
+// A cookie to set with Response::set_cookie(). Build one with new()
+// and fill in whichever attributes it needs:
+//
+//   let mut cookie = Cookie::new("session", &token);
+//   cookie.path = Some(String::from("/"));
+//   cookie.http_only = true;
+//   response.set_cookie(&cookie);
+//
+// The value goes out as it is, so anything that isn't a letter,
+// digit or simple punctuation (spaces, ; , " or \) has to be encoded
+// first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    // The path the cookie is sent back for, along with everything
+    // under it. None leaves it to the browser, which uses the
+    // directory of the page that set it.
+    pub path: Option<String>,
+    // How long the browser keeps it. None makes it a session cookie,
+    // gone when the browser closes. Zero deletes it straight away.
+    pub max_age: Option<Duration>,
+    // Keeps scripts on the page from reading it, which is what a
+    // session cookie wants.
+    pub http_only: bool,
+    // Only sends it back over https.
+    pub secure: bool,
+    pub same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new(name: &str, value: &str) -> Cookie {
+        Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            max_age: None,
+            http_only: false,
+            secure: false,
+            same_site: None,
+        }
+    }
+}
+
+// The cookie as the value of a Set-Cookie header.
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => write!(f, "; SameSite=Strict"),
+            Some(SameSite::Lax) => write!(f, "; SameSite=Lax"),
+            Some(SameSite::None) => write!(f, "; SameSite=None"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cookies(headers: &[&str]) -> HashMap<String, String> {
+        let headers: Vec<(String, String)> = headers
+            .iter()
+            .map(|value| (String::from("Cookie"), value.to_string()))
+            .collect();
+        parse(&headers)
+    }
+
+    #[test]
+    fn quoted_values_lose_their_quotes() {
+        let parsed = cookies(&["session=3f9a1c2e; theme=\"dark\""]);
+        assert_eq!(parsed["session"], "3f9a1c2e");
+        assert_eq!(parsed["theme"], "dark");
+    }
+
+    #[test]
+    fn whitespace_around_pairs_is_ignored() {
+        let parsed = cookies(&["  a = 1 ;b=2;   c =\"3\"  "]);
+        assert_eq!(parsed["a"], "1");
+        assert_eq!(parsed["b"], "2");
+        assert_eq!(parsed["c"], "3");
+    }
+
+    #[test]
+    fn pairs_without_a_name_or_an_equals_are_skipped() {
+        let parsed = cookies(&["flag; =orphan; ok=yes"]);
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed["ok"], "yes");
+    }
+
+    #[test]
+    fn the_first_of_a_repeated_name_wins_across_headers() {
+        let parsed = cookies(&["id=specific", "id=general; other=x"]);
+        assert_eq!(parsed["id"], "specific");
+        assert_eq!(parsed["other"], "x");
+    }
+
+    #[test]
+    fn a_cookie_formats_with_its_attributes() {
+        let mut cookie = Cookie::new("session", "abc");
+        cookie.path = Some(String::from("/"));
+        cookie.max_age = Some(Duration::from_secs(60));
+        cookie.http_only = true;
+        cookie.secure = true;
+        cookie.same_site = Some(SameSite::Lax);
+        assert_eq!(
+            cookie.to_string(),
+            "session=abc; Path=/; Max-Age=60; HttpOnly; Secure; SameSite=Lax"
+        );
+    }
+}
@@ -0,0 +1,199 @@
+// Cross-site request forgery: a page on some other site can have a
+// visitor's browser submit a form to us, and the browser sends our
+// cookies along with it, so as far as the session goes it's the
+// visitor asking. What the other site can't do is read our pages, so
+// a secret we put in our own forms, and check for when they come
+// back, tells a real submission from a forged one.
+//
+// The secret is a token kept in the session. Put it in each form
+// with add_to() and {{{csrf_field}}} in the template:
+//
+//   let mut values = HashMap::new();
+//   csrf::add_to(&mut values, session);
+//   let page = template::render_file("html/form.html", &values, Unmatched::Keep)?;
+//
+// With Config::csrf_protection on, the server checks it on every
+// request to a handler that could change something (anything but
+// GET, HEAD, OPTIONS and TRACE) and answers 403 if it's missing or
+// wrong. verify() does the same check for handlers that want to do
+// it themselves.
+use std::collections::HashMap;
+
+use crate::auth;
+use crate::html::html_escape;
+use crate::request::Request;
+use crate::session::{self, Session};
+use crate::url;
+
+// The form field the token is sent back in, and the session key it's
+// kept under.
+pub const FIELD: &str = "csrf_token";
+
+// Scripts sending JSON have no form to put it in, so they can send
+// it in this header instead.
+pub const HEADER: &str = "X-CSRF-Token";
+
+// The session's token, made the first time it's asked for.
+pub fn token(session: &Session) -> String {
+    if let Some(token) = session.get(FIELD) {
+        return token;
+    }
+    match session::random_id() {
+        Ok(token) => {
+            session.set(FIELD, &token);
+            token
+        }
+        // Without a token every form would be refused, but that's
+        // better than accepting forged ones.
+        Err(e) => {
+            error!("couldn't make a CSRF token: {}", e);
+            String::new()
+        }
+    }
+}
+
+// A hidden form field holding the token.
+pub fn field(session: &Session) -> String {
+    format!(
+        "<input type=\"hidden\" name=\"{}\" value=\"{}\">",
+        FIELD,
+        html_escape(&token(session))
+    )
+}
+
+// Adds csrf_token (the token) and csrf_field (the whole hidden
+// field, for {{{csrf_field}}}) to values for a template.
+pub fn add_to(values: &mut HashMap<String, String>, session: &Session) {
+    values.insert(String::from(FIELD), token(session));
+    values.insert(String::from("csrf_field"), field(session));
+}
+
+// Whether request carries its session's token, in the X-CSRF-Token
+// header or the csrf_token field of a form body. A request with no
+// session, or whose session never had a token, fails.
+pub fn verify(request: &Request) -> bool {
+    let expected = match request.session().and_then(|session| session.get(FIELD)) {
+        Some(token) if !token.is_empty() => token,
+        _ => return false,
+    };
+    let sent = match request.header(HEADER) {
+        Some(token) => Some(token.to_string()),
+        None => form_field(request, FIELD),
+    };
+    // Compared in constant time, so how quickly a guess is refused
+    // doesn't say how much of it was right.
+    sent.is_some_and(|sent| auth::same(sent.as_bytes(), expected.as_bytes()))
+}
+
+// Whether a method is one that's meant to change something, and so
+// gets checked.
+pub fn needs_check(method: &str) -> bool {
+    !matches!(method, "GET" | "HEAD" | "OPTIONS" | "TRACE")
+}
+
+// The first value of name in an application/x-www-form-urlencoded
+// body, which is what a browser sends a plain form as:
+//
+//   name=alice&csrf_token=3f9a1c2e&note=hi+there
+fn form_field(request: &Request, name: &str) -> Option<String> {
+    let content_type = request.header("Content-Type")?;
+    let mime = content_type.split(';').next().unwrap_or("").trim();
+    if !mime.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+        return None;
+    }
+    let body = std::str::from_utf8(&request.body).ok()?;
+    body.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let decode = |text: &str| url::percent_decode(&text.replace('+', " "));
+        if decode(key)? == name {
+            decode(value)
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::Response;
+    use crate::server::Server;
+    use crate::testing::RawRequest;
+
+    // The status line of the response in output, its session cookie
+    // (name=value) if it set one, and its body.
+    fn split(output: &[u8]) -> (String, Option<String>, String) {
+        let output = String::from_utf8_lossy(output);
+        let (head, body) = output.split_once("\r\n\r\n").unwrap();
+        let status = head.lines().next().unwrap_or("").to_string();
+        let cookie = head.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            let pair = value.split(';').next().unwrap_or("").trim();
+            key.eq_ignore_ascii_case("Set-Cookie")
+                .then(|| pair.to_string())
+        });
+        (status, cookie, body.to_string())
+    }
+
+    #[test]
+    fn only_methods_that_change_things_are_checked() {
+        for method in ["GET", "HEAD", "OPTIONS", "TRACE"] {
+            assert!(!needs_check(method), "{}", method);
+        }
+        for method in ["POST", "PUT", "PATCH", "DELETE"] {
+            assert!(needs_check(method), "{}", method);
+        }
+    }
+
+    #[test]
+    fn tokens_are_compared_whole() {
+        assert!(auth::same(b"3f9a1c2e", b"3f9a1c2e"));
+        assert!(!auth::same(b"3f9a1c2e", b"3f9a1c2f"));
+        assert!(!auth::same(b"3f9a1c2e", b"3f9a1c2"));
+        assert!(!auth::same(b"3f9a1c2", b"3f9a1c2e"));
+        assert!(!auth::same(b"", b"3f9a1c2e"));
+    }
+
+    #[test]
+    fn a_post_without_its_sessions_token_gets_a_403() {
+        let server = Server::builder()
+            .option("csrf_protection", "true")
+            .route("GET", "/form", |request| {
+                Response::text(200, &token(request.session().unwrap()))
+            })
+            .route("POST", "/form", |_| Response::text(200, "done"))
+            .build()
+            .unwrap();
+        let form = RawRequest::new("GET", "/form").header("Host", "x").build();
+        let (_, cookie, token) = split(&server.dispatch(&form));
+        let cookie = cookie.expect("no session cookie");
+        // Another visitor's token, which is no good in this session.
+        let (_, _, other) = split(&server.dispatch(&form));
+        assert_ne!(token, other);
+
+        let post = |headers: &[(&str, &str)], body: &str| {
+            let mut raw = RawRequest::new("POST", "/form").header("Host", "x");
+            for (name, value) in headers {
+                raw = raw.header(name, value);
+            }
+            split(&server.dispatch(&raw.body(body).build())).0
+        };
+        let ok = "HTTP/1.1 200 OK";
+        let forbidden = "HTTP/1.1 403 Forbidden";
+        let session = ("Cookie", cookie.as_str());
+        let form_type = ("Content-Type", "application/x-www-form-urlencoded");
+        assert_eq!(post(&[], ""), forbidden);
+        assert_eq!(post(&[session], ""), forbidden);
+        assert_eq!(post(&[(HEADER, &token)], ""), forbidden);
+        assert_eq!(post(&[session, (HEADER, "wrong")], ""), forbidden);
+        assert_eq!(post(&[session, (HEADER, &token[1..])], ""), forbidden);
+        assert_eq!(post(&[session, (HEADER, &other)], ""), forbidden);
+        assert_eq!(post(&[session, (HEADER, &token)], ""), ok);
+        let body = format!("note=hi+there&{}={}", FIELD, token);
+        assert_eq!(post(&[session, form_type], &body), ok);
+        assert_eq!(post(&[session, form_type], "note=hi"), forbidden);
+        // Only a form body is looked in, not any body that happens to
+        // have the field.
+        assert_eq!(post(&[session], &body), forbidden);
+    }
+}
@@ -0,0 +1,69 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::response::{self, Response};
+
+// What a handler registered with Server::route_fallible() returns
+// when it can't answer normally. The status and message go back to
+// the client, with the error page for the status if there's one
+// configured, the same as the server's own errors.
+//
+// Any other error converts into a 500, so a handler can use ? on
+// whatever it calls. What went wrong is logged rather than sent,
+// since it's about our insides and not the client's request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpError {
+    pub status: u16,
+    pub message: String,
+    // The error a 500 was converted from, for the log.
+    cause: Option<String>,
+}
+
+impl HttpError {
+    pub fn new(status: u16, message: &str) -> HttpError {
+        HttpError {
+            status,
+            message: message.to_string(),
+            cause: None,
+        }
+    }
+
+    // An error with the usual message for its status, like
+    // "404 Not Found".
+    pub fn status(status: u16) -> HttpError {
+        HttpError::new(status, &format!("{} {}", status, response::reason(status)))
+    }
+
+    // What caused it, if it came from some other error.
+    pub fn cause(&self) -> Option<&str> {
+        self.cause.as_deref()
+    }
+}
+
+// HttpError deliberately isn't an Error itself, or this would clash
+// with the From every type has for itself.
+impl<E: Error> From<E> for HttpError {
+    fn from(error: E) -> HttpError {
+        HttpError {
+            cause: Some(error.to_string()),
+            ..HttpError::status(500)
+        }
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.cause {
+            Some(cause) => write!(f, "{}: {}", self.message, cause),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+// The error as a plain text response. The server uses the configured
+// error pages instead when it's the one answering.
+impl From<HttpError> for Response {
+    fn from(error: HttpError) -> Response {
+        Response::text(error.status, &error.message)
+    }
+}
@@ -0,0 +1,40 @@
+use std::time::Duration;
+
+use crate::connection::Peer;
+use crate::request::Request;
+
+// The points in a connection's life that hooks registered with
+// Server::on_event() get told about, in the order they happen.
+#[derive(Debug)]
+pub enum Event<'a> {
+    // A client connected. Fired on the worker that picked the
+    // connection up, so a slow hook never holds up accepting.
+    ConnectionAccepted {
+        peer: Peer,
+    },
+    // A request's head was read and parsed, before it's routed.
+    RequestParsed {
+        peer: Peer,
+        request: &'a Request,
+    },
+    // A response finished going out. request is None when the
+    // request couldn't be parsed and we answered with an error.
+    // duration runs from when we started reading the request.
+    ResponseSent {
+        peer: Peer,
+        request: Option<&'a Request>,
+        status: u16,
+        duration: Duration,
+    },
+    // The connection is about to be closed. duration is how long
+    // it was open for.
+    ConnectionClosed {
+        peer: Peer,
+        duration: Duration,
+    },
+}
+
+// A hook gets every event, and can match on the ones it cares
+// about. Like handlers, hooks can be called from several worker
+// threads at once.
+pub type Hook = Box<dyn Fn(&Event) + Send + Sync>;
@@ -0,0 +1,699 @@
+use std::collections::HashMap;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+
+use crate::config::Config;
+use crate::glob;
+use crate::httpdate;
+use crate::language;
+use crate::listing;
+use crate::request::Request;
+use crate::response::{Body, Response};
+use crate::url;
+
+// Why a request path couldn't be turned into a file path.
+#[derive(Debug, PartialEq)]
+pub enum ResolveError {
+    // The path tried to climb out of the root with .. or a
+    // symlink, or asked for a dotfile while those are switched off.
+    Forbidden,
+    // The path isn't valid percent-encoded UTF-8.
+    BadPath,
+}
+
+// Serves the file at the URL path target. This is the one place
+// file contents are read for a response, so everything that decides
+// how a file goes out (content type, caching) lives here.
+//
+// Returns the status the request should fail with if the file
+// can't be served.
+//
+// With Config::fallback_roots the roots are tried in order and the
+// first one with something at target wins, so a file in the static
+// root overrides the one of the same name in a fallback. A directory
+// is the exception: it's made up of what every root has there, so an
+// override can replace one file in it without copying all the rest.
+//
+// request is the one being answered, for its Accept-Language header.
+pub fn serve(config: &Config, request: &Request, target: &str) -> Result<Response, u16> {
+    let mut dirs = Vec::new();
+    for root in roots(config) {
+        // Each root is checked on its own terms, so a path that would
+        // climb out of any of them is refused outright rather than
+        // being passed on to the next.
+        let file = match resolve(config, root, target) {
+            Ok(file) => file,
+            Err(ResolveError::Forbidden) => return Err(403),
+            Err(ResolveError::BadPath) => return Err(400),
+        };
+        if file.is_dir() {
+            dirs.push((root, file));
+        } else if dirs.is_empty() {
+            if let Some(response) = read_translated(config, request, root, &file) {
+                return response;
+            }
+        }
+    }
+    if dirs.is_empty() {
+        return Err(404);
+    }
+
+    // Relative links in an index page are resolved against the
+    // URL, so /docs has to become /docs/ or a link to style.css
+    // would point at /style.css instead of /docs/style.css.
+    if !target.ends_with('/') {
+        let location = url::percent_decode(target).ok_or(400u16)?;
+        return Ok(Response::redirect(
+            301,
+            &format!("{}/", url::percent_encode_path(&location)),
+        ));
+    }
+
+    let root = &config.static_root;
+    for name in &root.index_files {
+        for (root, dir) in &dirs {
+            if let Some(response) = read_translated(config, request, root, &dir.join(name)) {
+                return response;
+            }
+        }
+    }
+    if root.directory_listing {
+        let dirs: Vec<&Path> = dirs.iter().map(|(_, dir)| dir.as_path()).collect();
+        let sort = listing::Sort::from_query(request.query.as_deref());
+        return listing::render(config, &dirs, target, sort).map_err(|_| 404);
+    }
+    Err(404)
+}
+
+// The static root followed by any fallback roots, in the order
+// they're searched.
+fn roots(config: &Config) -> impl Iterator<Item = &Path> {
+    std::iter::once(config.static_root.path.as_path())
+        .chain(config.fallback_roots.iter().map(PathBuf::as_path))
+}
+
+// Reads file, or the translation of it the client would rather have
+// (see StaticRoot::languages). The choice is, in order: the best
+// translation the client accepts, the default language's, the file
+// itself, and the first translation there is. None if there's no
+// file and no translation either.
+fn read_translated(
+    config: &Config,
+    request: &Request,
+    root: &Path,
+    file: &Path,
+) -> Option<Result<Response, u16>> {
+    let translations = translations(config, file);
+    let languages: Vec<&str> = translations.iter().map(|(language, _)| *language).collect();
+    let accepted = request
+        .header("Accept-Language")
+        .and_then(|header| language::negotiate(header, &languages));
+    let default = config
+        .static_root
+        .default_language
+        .as_deref()
+        .filter(|default| languages.contains(default));
+
+    let (path, language) = match accepted.or(default) {
+        Some(language) => (translation(file, language), Some(language)),
+        None if file.is_file() => (file.to_path_buf(), None),
+        None => {
+            let (language, path) = translations.into_iter().next()?;
+            (path, Some(language))
+        }
+    };
+
+    // An index file or a translation could be a symlink of its own.
+    if inside_root(config, root, &path).is_err() {
+        return Some(Err(403));
+    }
+    let (compressed, compressions) = precompressed(config, request, &path);
+    let mut response = match compressed {
+        Some((copy, coding)) => {
+            if inside_root(config, root, &copy).is_err() {
+                return Some(Err(403));
+            }
+            // It's still the same file as far as the client knows, only
+            // sent compressed.
+            read(config, &copy).map(|mut response| {
+                response.set_header("Content-Type", content_type(&path));
+                response.set_header("Cache-Control", cache_control(config, &path));
+                response.set_header("Content-Encoding", coding);
+                response
+            })
+        }
+        None => read(config, &path),
+    };
+    if let Ok(response) = &mut response {
+        if let Some(language) = language {
+            response.set_header("Content-Language", language);
+        }
+        // Caches have to know the same URL gets a different file
+        // depending on the headers.
+        if !languages.is_empty() {
+            add_vary(response, "Accept-Language");
+        }
+        if compressions {
+            add_vary(response, "Accept-Encoding");
+        }
+    }
+    Some(response)
+}
+
+// The compressed copies looked for next to a file, as the coding
+// Accept-Encoding calls it and the extension added to the file's
+// name, best first.
+const COMPRESSIONS: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+// The compressed copy of file to send instead, with its coding, if
+// Config::precompressed is on and there's an up to date one the client
+// accepts. Also says whether there are any copies at all, even ones
+// that weren't picked, since then what gets sent depends on
+// Accept-Encoding.
+fn precompressed(
+    config: &Config,
+    request: &Request,
+    file: &Path,
+) -> (Option<(PathBuf, &'static str)>, bool) {
+    if !config.precompressed {
+        return (None, false);
+    }
+    let modified = fs::metadata(file)
+        .and_then(|metadata| metadata.modified())
+        .ok();
+    let accepted = request.header("Accept-Encoding").unwrap_or("");
+    let mut any = false;
+    for (coding, extension) in COMPRESSIONS {
+        let mut name = file.as_os_str().to_owned();
+        name.push(".");
+        name.push(extension);
+        let copy = PathBuf::from(name);
+        let copied = match fs::metadata(&copy).and_then(|metadata| metadata.modified()) {
+            Ok(copied) => copied,
+            Err(_) => continue,
+        };
+        any = true;
+        if !accepts_coding(accepted, coding) {
+            continue;
+        }
+        // Editing the file and forgetting to make the copy again is
+        // easy to do, and without this the edit would never show up
+        // for anyone whose browser takes the copy. A time we can't
+        // read counts as stale too.
+        if modified.is_none_or(|modified| copied < modified) {
+            debug!(
+                "{} is older than the file it's of, not using it",
+                copy.display()
+            );
+            continue;
+        }
+        return (Some((copy, coding)), true);
+    }
+    (None, any)
+}
+
+// Whether an Accept-Encoding header takes coding, by name or as *,
+// with a q-value above 0.
+fn accepts_coding(header: &str, coding: &str) -> bool {
+    let mut star = false;
+    for item in header.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or("").trim();
+        let q = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .next()
+            .map_or(1.0, |q| q.trim().parse().unwrap_or(0.0));
+        if name.eq_ignore_ascii_case(coding) {
+            return q > 0.0;
+        }
+        if name == "*" {
+            star = q > 0.0;
+        }
+    }
+    star
+}
+
+// Adds name to the response's Vary header, keeping what's there.
+fn add_vary(response: &mut Response, name: &str) {
+    let vary = match response.header("Vary") {
+        Some(vary) => format!("{}, {}", vary, name),
+        None => name.to_string(),
+    };
+    response.set_header("Vary", &vary);
+}
+
+// The translations of file there are, in the order of
+// StaticRoot::languages, with the language each is in.
+fn translations<'a>(config: &'a Config, file: &Path) -> Vec<(&'a str, PathBuf)> {
+    config
+        .static_root
+        .languages
+        .iter()
+        .map(|language| (language.as_str(), translation(file, language)))
+        .filter(|(_, path)| path.is_file())
+        .collect()
+}
+
+// Where the translation of file into language would be:
+// html/index.fr.html for html/index.html, html/README.fr for
+// html/README.
+fn translation(file: &Path, language: &str) -> PathBuf {
+    let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match file.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, language, extension.to_string_lossy()),
+        None => format!("{}.{}", stem, language),
+    };
+    file.with_file_name(name)
+}
+
+// Files at least this big are sent straight from the open file
+// instead of being read into memory first.
+pub const SEND_FROM_DISK: u64 = 1024 * 1024;
+
+// Reads a file into a response, or fails with 404 if it isn't a
+// readable file after all.
+//
+// Files can change while we're serving them, so everything comes
+// from a single open handle: the is-it-a-file check is made on the
+// file we're about to read rather than whatever the path points at,
+// and the body is whatever read_to_end() actually got. The file's
+// size only sizes the buffer. Content-Length is worked out from the
+// bytes in the body when the response is written, so a file that is
+// truncated or grows halfway through can't make the headers and body
+// disagree.
+//
+// A big file is sent from the handle instead (Body::Handle), with
+// the length it had when we opened it. Replacing it the way PUT does,
+// with a rename, doesn't affect a handle that's already open, but
+// one cut short in place while it's going out can't be sent whole,
+// and the connection is closed partway through.
+fn read(config: &Config, file: &Path) -> Result<Response, u16> {
+    let mut handle = File::open(file).map_err(|_| 404u16)?;
+    let metadata = handle.metadata().map_err(|_| 404u16)?;
+    if !metadata.is_file() {
+        return Err(404);
+    }
+
+    let mut response = Response::new(200);
+    response.set_header("Content-Type", content_type(file));
+    response.set_header("Cache-Control", cache_control(config, file));
+    if let Ok(modified) = metadata.modified() {
+        response.set_header("Last-Modified", &httpdate::format(modified));
+    }
+
+    if metadata.len() >= SEND_FROM_DISK {
+        if config.sniff_content_type && content_type(file) == UNKNOWN_TYPE {
+            let mut start = Vec::with_capacity(SNIFF_LEN);
+            (&handle)
+                .take(SNIFF_LEN as u64)
+                .read_to_end(&mut start)
+                .map_err(|_| 404u16)?;
+            handle.seek(SeekFrom::Start(0)).map_err(|_| 404u16)?;
+            response.set_header("Content-Type", sniff(&start));
+        }
+        let etag = match lookup_etag(file, &metadata) {
+            Some(etag) => etag,
+            None => {
+                let etag = hash_file(&mut handle).map_err(|_| 404u16)?;
+                remember_etag(file, &metadata, &etag);
+                etag
+            }
+        };
+        response.set_header("ETag", &etag);
+        response.body = Body::Handle {
+            file: Arc::new(handle),
+            offset: 0,
+            len: metadata.len(),
+        };
+        return Ok(response);
+    }
+
+    // Raw bytes rather than read_to_string() because the
+    // file could be an image or anything else that isn't
+    // valid UTF-8.
+    let mut contents = Vec::with_capacity(metadata.len() as usize);
+    handle.read_to_end(&mut contents).map_err(|_| 404u16)?;
+
+    if config.sniff_content_type && content_type(file) == UNKNOWN_TYPE {
+        response.set_header("Content-Type", sniff(&contents));
+    }
+    response.set_header("ETag", &cached_etag(file, &metadata, &contents));
+    response.body = Body::Bytes(contents);
+    Ok(response)
+}
+
+// Turns the path part of a request target (/css/site.css) into
+// a path under root (html/css/site.css).
+//
+// This is mostly lexical: we refuse anything that could reach
+// outside the root by its name alone, then inside_root() checks
+// nothing on the way is a symlink out of it.
+// Every segment is checked, not just the last one, so
+// /.git/config is caught as well as /.env.
+pub fn resolve(config: &Config, root: &Path, request_path: &str) -> Result<PathBuf, ResolveError> {
+    let decoded = url::percent_decode(request_path).ok_or(ResolveError::BadPath)?;
+
+    let mut path = root.to_path_buf();
+    for segment in decoded.split('/') {
+        // Empty segments come from the leading / or from //,
+        // and . means "this directory", so both are no-ops.
+        if segment.is_empty() || segment == "." {
+            continue;
+        }
+        // .. is never allowed, even when dotfiles are. Windows
+        // also treats \ as a separator so a segment containing
+        // one could smuggle a .. past us.
+        if segment == ".." || segment.contains('\\') {
+            return Err(ResolveError::Forbidden);
+        }
+        if segment.starts_with('.') && !config.serve_dotfiles {
+            return Err(ResolveError::Forbidden);
+        }
+        path.push(segment);
+    }
+
+    inside_root(config, root, &path)?;
+    Ok(path)
+}
+
+// The checks in resolve() only look at the path as written. A symlink
+// under root (html/leak -> /etc) can still lead somewhere else
+// entirely, so this asks the filesystem where path really ends up and
+// refuses it if that's outside root.
+//
+// A path that doesn't exist yet (a PUT making a new file) is judged
+// by the deepest part of it that does, since that's where it would
+// be created. Config::follow_symlinks turns the check off for sites
+// that link to things outside the root on purpose.
+pub fn inside_root(config: &Config, root: &Path, path: &Path) -> Result<(), ResolveError> {
+    if config.follow_symlinks {
+        return Ok(());
+    }
+    // Without a root there's nothing to serve anyway, and that's a
+    // 404 for whoever handles the missing file.
+    let root = match fs::canonicalize(root) {
+        Ok(root) => root,
+        Err(_) => return Ok(()),
+    };
+
+    let mut existing = path;
+    loop {
+        if let Ok(real) = fs::canonicalize(existing) {
+            return if real.starts_with(&root) {
+                Ok(())
+            } else {
+                Err(ResolveError::Forbidden)
+            };
+        }
+        existing = match existing.parent() {
+            Some(parent) => parent,
+            None => return Ok(()),
+        };
+    }
+}
+
+// An ETag names one version of a file, so it has to change whenever
+// the file does. It's a hash of the contents (64 bit FNV-1a), so it
+// only changes when the bytes do: the same file gets the same ETag
+// after a restart, a redeploy that rewrites it unchanged, or on
+// another server with a copy of it. That's what lets a download
+// interrupted on one of them resume with If-Range on any of them.
+//
+// It's a strong ETag, since the same hash means the same bytes (as
+// near as makes no difference), so ranges of it can be stitched
+// together.
+pub fn etag(contents: &[u8]) -> String {
+    format!(
+        "\"{:016x}-{:x}\"",
+        fnv(FNV_OFFSET, contents),
+        contents.len()
+    )
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+// Carries an FNV-1a hash on over bytes, so a file can be hashed a
+// piece at a time.
+fn fnv(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// etag() of everything left in file, read a piece at a time rather
+// than all at once.
+fn hash_file(file: &mut File) -> io::Result<String> {
+    let mut hash = FNV_OFFSET;
+    let mut len = 0;
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+        hash = fnv(hash, &chunk[..read]);
+        len += read;
+    }
+    Ok(format!("\"{:016x}-{:x}\"", hash, len))
+}
+
+// The ETag of the file at path, or None if it can't be read.
+pub fn etag_of(path: &Path) -> Option<String> {
+    let mut handle = File::open(path).ok()?;
+    let metadata = handle.metadata().ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+    if let Some(etag) = lookup_etag(path, &metadata) {
+        return Some(etag);
+    }
+    let etag = hash_file(&mut handle).ok()?;
+    remember_etag(path, &metadata, &etag);
+    Some(etag)
+}
+
+// Hashing a big file on every request adds up, so hashes are kept
+// for as long as the file's size and modification time stay the
+// same. The cache is cleared rather than grown past this many files.
+const ETAG_CACHE_SIZE: usize = 4096;
+
+type EtagCache = HashMap<PathBuf, (u64, Option<SystemTime>, String)>;
+
+fn etag_cache() -> &'static Mutex<EtagCache> {
+    static CACHE: OnceLock<Mutex<EtagCache>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn lookup_etag(path: &Path, metadata: &Metadata) -> Option<String> {
+    let cache = etag_cache().lock().unwrap_or_else(|e| e.into_inner());
+    match cache.get(path) {
+        Some((len, modified, etag))
+            if *len == metadata.len() && *modified == metadata.modified().ok() =>
+        {
+            Some(etag.clone())
+        }
+        _ => None,
+    }
+}
+
+fn cached_etag(path: &Path, metadata: &Metadata, contents: &[u8]) -> String {
+    if let Some(etag) = lookup_etag(path, metadata) {
+        return etag;
+    }
+    let etag = etag(contents);
+    remember_etag(path, metadata, &etag);
+    etag
+}
+
+fn remember_etag(path: &Path, metadata: &Metadata, etag: &str) {
+    let mut cache = etag_cache().lock().unwrap_or_else(|e| e.into_inner());
+    if cache.len() >= ETAG_CACHE_SIZE {
+        cache.clear();
+    }
+    cache.insert(
+        path.to_path_buf(),
+        (metadata.len(), metadata.modified().ok(), etag.to_string()),
+    );
+}
+
+// The Cache-Control header value for a file we're serving.
+pub fn cache_control<'a>(config: &'a Config, path: &Path) -> &'a str {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+
+    match &config.immutable_pattern {
+        Some(pattern) if glob::matches(pattern, name) => "public, max-age=31536000, immutable",
+        _ => &config.cache_control,
+    }
+}
+
+// What a file we can't tell the type of is sent as: raw bytes, which
+// browsers download instead of guessing at.
+pub const UNKNOWN_TYPE: &str = "application/octet-stream";
+
+// The MIME type to send for a file, going off its extension.
+// Anything we don't recognise is sent as raw bytes so the browser
+// downloads it instead of guessing, unless
+// Config::sniff_content_type is on (see sniff()).
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        // Pages, styles and scripts. Browsers only run a module
+        // script if it's sent as JavaScript, whatever it's called.
+        Some("html") | Some("htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") | Some("mjs") | Some("cjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        // Source maps are JSON, and the browser's dev tools want
+        // them as that.
+        Some("map") => "application/json",
+        Some("webmanifest") => "application/manifest+json",
+        Some("wasm") => "application/wasm",
+        Some("xml") => "application/xml",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("md") => "text/markdown; charset=utf-8",
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+
+        // Images.
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("ico") => "image/x-icon",
+        Some("webp") => "image/webp",
+        Some("avif") => "image/avif",
+        Some("apng") => "image/apng",
+        Some("bmp") => "image/bmp",
+
+        // Fonts.
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("otf") => "font/otf",
+
+        // Audio and video.
+        Some("mp3") => "audio/mpeg",
+        Some("ogg") | Some("oga") => "audio/ogg",
+        Some("wav") => "audio/wav",
+        Some("flac") => "audio/flac",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("vtt") => "text/vtt; charset=utf-8",
+
+        _ => UNKNOWN_TYPE,
+    }
+}
+
+// How much of a file sniff() looks at.
+const SNIFF_LEN: usize = 512;
+
+// Guesses the type of a file with no extension, or one we don't
+// know, from the first bytes of it. Only a few types are worth it:
+// the ones that start with a signature of their own, HTML, and plain
+// text for the extensionless READMEs and LICENSEs of the world.
+// Anything else stays raw bytes.
+pub fn sniff(contents: &[u8]) -> &'static str {
+    let start = &contents[..contents.len().min(SNIFF_LEN)];
+    let signatures: [(&[u8], &str); 6] = [
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"\xef\xbb\xbf", "text/plain; charset=utf-8"),
+    ];
+    for (signature, kind) in signatures {
+        if start.starts_with(signature) {
+            return kind;
+        }
+    }
+
+    let trimmed = match start.iter().position(|b| !b.is_ascii_whitespace()) {
+        Some(i) => &start[i..],
+        None => start,
+    };
+    let html = ["<!doctype html", "<html", "<head", "<body", "<!--"]
+        .iter()
+        .any(|tag| {
+            trimmed.len() >= tag.len() && trimmed[..tag.len()].eq_ignore_ascii_case(tag.as_bytes())
+        });
+    if html {
+        return "text/html; charset=utf-8";
+    }
+
+    // Text is UTF-8 without control characters, apart from the ones
+    // that are just layout. SNIFF_LEN can cut the last character in
+    // half, which doesn't count against it.
+    let text = match std::str::from_utf8(start) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    };
+    let layout = |b: &u8| matches!(b, b'\t' | b'\n' | b'\r' | b'\x0c' | b'\x1b');
+    if text && !start.iter().any(|b| b.is_ascii_control() && !layout(b)) {
+        return "text/plain; charset=utf-8";
+    }
+    UNKNOWN_TYPE
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::content_type;
+    use crate::server::Server;
+    use crate::testing::{temp_dir, RawRequest};
+
+    // The status line a server on root with follow_symlinks set to
+    // follow gives a GET for path.
+    #[cfg(unix)]
+    fn status(root: &std::path::Path, follow: bool, path: &str) -> String {
+        let server = Server::builder()
+            .static_root(root)
+            .option("follow_symlinks", if follow { "true" } else { "false" })
+            .build()
+            .unwrap();
+        let raw = RawRequest::new("GET", path).header("Host", "a").build();
+        let output = server.dispatch(&raw);
+        let output = String::from_utf8_lossy(&output);
+        output.lines().next().unwrap_or("").to_string()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_symlink_out_of_the_root_is_only_followed_when_allowed() {
+        let root = temp_dir("symlink-out-of-root");
+        std::os::unix::fs::symlink("/etc", root.join("leak")).unwrap();
+        assert_eq!(
+            status(&root, false, "/leak/passwd"),
+            "HTTP/1.1 403 Forbidden"
+        );
+        assert_eq!(status(&root, true, "/leak/passwd"), "HTTP/1.1 200 OK");
+    }
+
+    #[test]
+    fn newer_file_types_have_their_content_types() {
+        let cases = [
+            ("photo.webp", "image/webp"),
+            ("photo.AVIF", "image/avif"),
+            ("font.woff2", "font/woff2"),
+            ("app.mjs", "text/javascript; charset=utf-8"),
+            ("app.js.map", "application/json"),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(content_type(Path::new(name)), expected, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+// Working out who a request really came from when the server sits
+// behind a proxy. The proxy is what connects to us, so the peer
+// address is always the proxy's; it tells us about the client in
+// X-Forwarded-For, X-Forwarded-Proto and X-Forwarded-Host.
+//
+// Anyone can send those headers, though, so they're only believed
+// when the connection comes from one of Config::trusted_proxies.
+// From anyone else they're ignored and the peer is the client.
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+use crate::config::Config;
+use crate::connection::Peer;
+use crate::request::Request;
+
+// The client a request came from, as far as we can tell. See
+// Request::client().
+#[derive(Debug, Clone, PartialEq)]
+pub struct Client {
+    // The client's IP address. None for a request over a Unix
+    // socket that nobody we trust vouched for.
+    pub addr: Option<IpAddr>,
+    // "http" or "https": how the client reached us, which is https
+    // when a proxy in front took care of TLS.
+    pub scheme: String,
+    // The host the client asked for: X-Forwarded-Host from a trusted
+    // proxy, otherwise the Host header.
+    pub host: Option<String>,
+}
+
+impl Client {
+    // The client as the connection alone shows it, with nothing
+    // taken from X-Forwarded-* headers.
+    pub fn direct(peer: Peer, request: &Request) -> Client {
+        Client {
+            addr: match peer {
+                Peer::Tcp(addr) => Some(addr.ip()),
+                Peer::Unix => None,
+            },
+            scheme: String::from("http"),
+            host: request.header("Host").map(str::to_string),
+        }
+    }
+}
+
+// The address, or "unix" without one, the same as Peer shows.
+impl fmt::Display for Client {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.addr {
+            Some(addr) => write!(f, "{}", addr),
+            None => write!(f, "unix"),
+        }
+    }
+}
+
+// Works out the client for a request that arrived from peer.
+pub fn client(config: &Config, peer: Peer, request: &Request) -> Client {
+    let mut client = Client::direct(peer, request);
+    let trusted = match peer {
+        Peer::Tcp(addr) => is_trusted(config, addr.ip()),
+        // A Unix socket can only be reached from this machine, so
+        // it's trusted along with the loopback addresses.
+        Peer::Unix => config.trusted_proxies.iter().any(IpAddr::is_loopback),
+    };
+    if !trusted {
+        return client;
+    }
+
+    // Each proxy adds the address it got the request from to the end
+    // of the list, so reading from the right, the first address that
+    // isn't one of our proxies is the client. Anything to the left of
+    // that came from the client itself and could say anything. A
+    // value we can't read stops the walk at the last hop we trust.
+    let mut forwarded_for = Vec::new();
+    for value in header_values(request, "X-Forwarded-For") {
+        forwarded_for.extend(value.split(',').map(str::trim));
+    }
+    for hop in forwarded_for.into_iter().rev() {
+        let addr = match parse_addr(hop) {
+            Some(addr) => addr,
+            None => break,
+        };
+        client.addr = Some(addr);
+        if !is_trusted(config, addr) {
+            break;
+        }
+    }
+
+    // These are set by the proxy nearest us, which goes last if
+    // there's more than one.
+    if let Some(scheme) = last_value(request, "X-Forwarded-Proto") {
+        let scheme = scheme.to_ascii_lowercase();
+        if scheme == "http" || scheme == "https" {
+            client.scheme = scheme;
+        }
+    }
+    if let Some(host) = last_value(request, "X-Forwarded-Host") {
+        if !host.is_empty() {
+            client.host = Some(host.to_string());
+        }
+    }
+
+    client
+}
+
+fn is_trusted(config: &Config, addr: IpAddr) -> bool {
+    config.trusted_proxies.contains(&addr)
+}
+
+fn header_values<'a>(request: &'a Request, name: &'a str) -> impl Iterator<Item = &'a str> {
+    request
+        .headers
+        .iter()
+        .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+fn last_value<'a>(request: &'a Request, name: &'a str) -> Option<&'a str> {
+    header_values(request, name)
+        .last()
+        .and_then(|value| value.rsplit(',').next())
+        .map(str::trim)
+}
+
+// An address in X-Forwarded-For, which some proxies send with a port
+// (1.2.3.4:5678, [::1]:5678).
+fn parse_addr(hop: &str) -> Option<IpAddr> {
+    hop.parse::<IpAddr>()
+        .ok()
+        .or_else(|| hop.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::RawRequest;
+
+    const PROXY: &str = "10.0.0.1";
+
+    fn peer(addr: &str) -> Peer {
+        Peer::Tcp(SocketAddr::new(addr.parse().unwrap(), 40000))
+    }
+
+    fn behind_proxy() -> Config {
+        Config {
+            trusted_proxies: vec![PROXY.parse().unwrap()],
+            ..Config::default()
+        }
+    }
+
+    fn forwarded(headers: &[(&str, &str)]) -> Request {
+        let mut raw = RawRequest::new("GET", "/").header("Host", "inside.local");
+        for (name, value) in headers {
+            raw = raw.header(name, value);
+        }
+        Request::parse(&raw.build()).unwrap()
+    }
+
+    fn claims() -> Request {
+        forwarded(&[
+            ("X-Forwarded-For", "203.0.113.7"),
+            ("X-Forwarded-Proto", "https"),
+            ("X-Forwarded-Host", "example.com"),
+        ])
+    }
+
+    #[test]
+    fn headers_from_an_untrusted_peer_are_ignored() {
+        let request = claims();
+        let stranger = client(&behind_proxy(), peer("198.51.100.2"), &request);
+        assert_eq!(stranger.addr, Some("198.51.100.2".parse().unwrap()));
+        assert_eq!(stranger.scheme, "http");
+        assert_eq!(stranger.host.as_deref(), Some("inside.local"));
+
+        let unconfigured = client(&Config::default(), peer(PROXY), &request);
+        assert_eq!(unconfigured, Client::direct(peer(PROXY), &request));
+    }
+
+    #[test]
+    fn headers_from_a_trusted_proxy_are_believed() {
+        let client = client(&behind_proxy(), peer(PROXY), &claims());
+        assert_eq!(client.addr, Some("203.0.113.7".parse().unwrap()));
+        assert_eq!(client.scheme, "https");
+        assert_eq!(client.host.as_deref(), Some("example.com"));
+    }
+
+    #[test]
+    fn the_client_is_the_first_untrusted_hop_from_the_right() {
+        // The client made up the first address; 10.0.0.2 is another
+        // proxy of ours, so the client is the one that reached it.
+        let mut config = behind_proxy();
+        config.trusted_proxies.push("10.0.0.2".parse().unwrap());
+        let request = forwarded(&[("X-Forwarded-For", "1.1.1.1, 203.0.113.7:5678, 10.0.0.2")]);
+        let client = client(&config, peer(PROXY), &request);
+        assert_eq!(client.addr, Some("203.0.113.7".parse().unwrap()));
+    }
+
+    #[test]
+    fn a_scheme_other_than_http_or_https_is_ignored() {
+        let request = forwarded(&[("X-Forwarded-Proto", "gopher")]);
+        let client = client(&behind_proxy(), peer(PROXY), &request);
+        assert_eq!(client.scheme, "http");
+    }
+}
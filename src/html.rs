@@ -0,0 +1,50 @@
+// Escaping text for HTML, so whatever's in it shows up as text
+// instead of being read as markup.
+
+// Replaces the five characters that mean something in HTML with
+// entities. That's enough for text between tags and for attribute
+// values in either kind of quotes, so anything from a client (a
+// query parameter, a form field, a file name) can go into a page
+// this way without being able to add tags or scripts of its own.
+pub fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_five_characters_are_escaped() {
+        assert_eq!(html_escape("&<>\"'"), "&amp;&lt;&gt;&quot;&#39;");
+        assert_eq!(
+            html_escape("<a href='x' title=\"y\">"),
+            "&lt;a href=&#39;x&#39; title=&quot;y&quot;&gt;"
+        );
+    }
+
+    #[test]
+    fn an_entity_is_escaped_rather_than_left_alone() {
+        assert_eq!(html_escape("&lt;"), "&amp;lt;");
+    }
+
+    #[test]
+    fn everything_else_is_left_as_it_is() {
+        assert_eq!(
+            html_escape("plain text, 100% — ünïcode"),
+            "plain text, 100% — ünïcode"
+        );
+        assert_eq!(html_escape(""), "");
+    }
+}
@@ -0,0 +1,136 @@
+// HTTP dates, like the ones in Last-Modified and If-Modified-Since:
+//
+//   Sun, 06 Nov 1994 08:49:37 GMT
+//
+// They're always in GMT and only go down to the second.
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// Formats time as an HTTP date. Anything before 1970 comes out as
+// the epoch, which no file we serve is going to be older than.
+pub fn format(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0);
+    let days = seconds / 86400;
+    let (year, month, day) = civil_from_days(days as i64);
+    let rest = seconds % 86400;
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        // 1 January 1970 was a Thursday, hence DAYS starting there.
+        DAYS[(days % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
+
+// Parses an HTTP date. Only the IMF-fixdate form above is accepted;
+// the two obsolete forms the spec lists are ignored, which just means
+// a condition that uses one is treated as if it wasn't there.
+pub fn parse(text: &str) -> Option<SystemTime> {
+    let mut parts = text.split_whitespace();
+    let _weekday = parts.next()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|&name| name == month)? as u32 + 1;
+    let year: i64 = parts.next()?.parse().ok()?;
+
+    let mut clock = parts.next()?.split(':');
+    let hour: u64 = clock.next()?.parse().ok()?;
+    let minute: u64 = clock.next()?.parse().ok()?;
+    let second: u64 = clock.next()?.parse().ok()?;
+
+    if parts.next()? != "GMT" || parts.next().is_some() {
+        return None;
+    }
+    // The year is four digits in an IMF-fixdate. One bigger than that
+    // would overflow the seconds below, or SystemTime itself.
+    if !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    if !(1970..=9999).contains(&year) {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day) as u64;
+    let seconds = days * 86400 + hour * 3600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+// Rounds time down to the second, since that's all an HTTP date can
+// say. Without this a file modified at 12:00:00.5 would always count
+// as newer than a client's If-Modified-Since of 12:00:00.
+pub fn truncate(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => UNIX_EPOCH + Duration::from_secs(since.as_secs()),
+        Err(_) => UNIX_EPOCH,
+    }
+}
+
+// Days since 1970-01-01 to (year, month, day), and back again. These
+// are Howard Hinnant's algorithms, which work in 400 year "eras" so
+// leap years fall out of the arithmetic.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = month as i64;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_format_and_parse_back() {
+        let time = UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(format(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 GMT"), Some(time));
+        assert_eq!(parse(&format(UNIX_EPOCH)), Some(UNIX_EPOCH));
+    }
+
+    #[test]
+    fn the_obsolete_forms_and_nonsense_dont_parse() {
+        assert_eq!(parse("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+        assert_eq!(parse("Sun Nov  6 08:49:37 1994"), None);
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 PST"), None);
+        assert_eq!(parse("Sun, 32 Nov 1994 08:49:37 GMT"), None);
+        assert_eq!(parse("Sun, 06 Nov 1994 24:00:00 GMT"), None);
+        assert_eq!(parse(""), None);
+    }
+
+    #[test]
+    fn years_out_of_range_dont_parse() {
+        assert_eq!(parse("Thu, 01 Jan 1969 00:00:00 GMT"), None);
+        assert_eq!(parse("Thu, 01 Jan 10000 00:00:00 GMT"), None);
+        assert_eq!(parse("Thu, 01 Jan 300000000000 00:00:00 GMT"), None);
+        assert!(parse("Fri, 31 Dec 9999 23:59:59 GMT").is_some());
+    }
+}
@@ -0,0 +1,89 @@
+// Accept-Language, where the client lists the languages it would
+// like a page in, best first or with q-values to say how much:
+//
+//   Accept-Language: fr-CH, fr;q=0.9, en;q=0.8, *;q=0.5
+//
+// A site with its pages in several languages (StaticRoot::languages)
+// uses it to pick which translation of a file to send.
+use std::cmp::Ordering;
+
+// Picks whichever of available the client would like best, going by
+// its Accept-Language header, or None if it accepts none of them.
+//
+// A range matches a language that's the same or more specific, so en
+// gets en-US if that's all there is, the way RFC 4647 filtering
+// works. The other way round, en-US settling for en, comes last for
+// each range, since a close relation beats whatever the client put
+// next. Ties go to whichever comes first in available.
+pub fn negotiate<'a>(header: &str, available: &[&'a str]) -> Option<&'a str> {
+    let ranges = parse(header);
+    // A q of 0 means "not this one", even if * says anything goes.
+    let refused = |language: &str| {
+        ranges
+            .iter()
+            .any(|(range, q)| *q <= 0.0 && *range != "*" && closeness(range, language).is_some())
+    };
+
+    for (range, q) in &ranges {
+        if *q <= 0.0 {
+            break;
+        }
+        let best = available
+            .iter()
+            .filter(|language| !refused(language))
+            .filter_map(|language| closeness(range, language).map(|close| (close, *language)))
+            .min_by_key(|(close, _)| *close);
+        if let Some((_, language)) = best {
+            return Some(language);
+        }
+    }
+    None
+}
+
+// The ranges in header with their q-values, the most wanted first.
+// Ranges with the same q keep the order they were sent in. A q-value
+// that isn't a number makes that range count for nothing.
+fn parse(header: &str) -> Vec<(&str, f32)> {
+    let mut ranges = Vec::new();
+    for item in header.split(',') {
+        let mut parts = item.split(';');
+        let range = parts.next().unwrap_or("").trim();
+        if range.is_empty() {
+            continue;
+        }
+        let mut q = 1.0;
+        for param in parts {
+            if let Some(value) = param.trim().strip_prefix("q=") {
+                q = value.trim().parse().unwrap_or(0.0);
+            }
+        }
+        ranges.push((range, q));
+    }
+    // sort_by is stable, which is what keeps equal ranges in order.
+    ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+    ranges
+}
+
+// How well range matches language, lower being better, or None if it
+// doesn't at all. Tags are compared ignoring case (en-us is en-US).
+fn closeness(range: &str, language: &str) -> Option<u8> {
+    if range == "*" {
+        Some(3)
+    } else if range.eq_ignore_ascii_case(language) {
+        Some(0)
+    } else if is_prefix(range, language) {
+        Some(1)
+    } else if is_prefix(language, range) {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+// Whether tag is a more specific form of prefix: en-US of en, but not
+// eng of en.
+fn is_prefix(prefix: &str, tag: &str) -> bool {
+    tag.len() > prefix.len()
+        && tag.as_bytes()[prefix.len()] == b'-'
+        && tag[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
@@ -0,0 +1,54 @@
+// The pieces of the server live in their own modules so the
+// binary in main.rs only has to wire them together.
+//
+// log comes first so its macros can be used in every module after it.
+#[macro_use]
+pub mod log;
+
+pub mod auth;
+pub mod builder;
+pub mod capture;
+pub mod conditional;
+pub mod config;
+pub mod connection;
+pub mod cookie;
+pub mod csrf;
+pub mod error;
+pub mod events;
+pub mod files;
+pub mod forwarded;
+pub mod glob;
+pub mod html;
+pub mod httpdate;
+pub mod language;
+pub mod listing;
+pub mod ranges;
+pub mod request;
+pub mod response;
+pub mod robots;
+pub mod server;
+pub mod session;
+pub mod signals;
+pub mod template;
+#[cfg(test)]
+mod testing;
+pub mod thread_pool;
+pub mod url;
+pub mod writes;
+
+pub use auth::{Authenticator, BasicAuth};
+pub use builder::ServerBuilder;
+pub use config::{Config, ConfigError, StaticRoot};
+pub use connection::{Connection, MemoryConnection, Peer};
+pub use cookie::{Cookie, SameSite};
+pub use error::HttpError;
+pub use events::Event;
+pub use forwarded::Client;
+pub use html::html_escape;
+pub use request::{BodyReader, ParseError, Request};
+pub use response::Response;
+pub use server::{
+    handle_connnection, Bound, FallibleHandler, Handler, Rewriter, Server, StreamingHandler,
+};
+pub use session::Session;
+pub use thread_pool::ThreadPool;
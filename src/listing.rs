@@ -0,0 +1,217 @@
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::config::Config;
+use crate::html::html_escape;
+use crate::httpdate;
+use crate::response::Response;
+use crate::url;
+
+// What the listing can be sorted by, from ?sort= in the query.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sort {
+    // A to Z. The default.
+    Name,
+    // Biggest first.
+    Size,
+    // Newest first.
+    Date,
+}
+
+impl Sort {
+    // The order a query asks for, or by name if it doesn't say (or
+    // says something we don't know).
+    pub fn from_query(query: Option<&str>) -> Sort {
+        let value = query
+            .unwrap_or("")
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("sort="));
+        match value {
+            Some("size") => Sort::Size,
+            Some("date") => Sort::Date,
+            _ => Sort::Name,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Sort::Name => "name",
+            Sort::Size => "size",
+            Sort::Date => "date",
+        }
+    }
+}
+
+struct Entry {
+    name: String,
+    dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+// Builds an HTML page listing everything in dirs, which are the
+// same directory in each static root that has it, with the size and
+// modification time of each. target is the URL path of the
+// directory, which always ends in a / by the time we get here, so
+// plain relative links work.
+//
+// Directories come first, then files, each sorted the way sort says.
+pub fn render(config: &Config, dirs: &[&Path], target: &str, sort: Sort) -> io::Result<Response> {
+    let mut entries = Vec::new();
+    let mut seen = HashSet::new();
+    for dir in dirs {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // Listing a dotfile we'd refuse to serve would just be
+            // telling people it's there.
+            if name.starts_with('.') && !config.serve_dotfiles {
+                continue;
+            }
+            // A name in more than one root is only listed once, as
+            // the one that would be served: the first root's.
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            entries.push(Entry {
+                name,
+                dir: metadata.is_dir(),
+                size: metadata.len(),
+                modified: metadata.modified().ok(),
+            });
+        }
+    }
+
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    match sort {
+        Sort::Name => {}
+        // sort_by_key is stable, so equal ones stay in name order.
+        Sort::Size => entries.sort_by_key(|entry| Reverse(entry.size)),
+        Sort::Date => entries.sort_by_key(|entry| Reverse(entry.modified)),
+    }
+    entries.sort_by_key(|entry| !entry.dir);
+
+    // target is still percent-encoded, which isn't much use for
+    // reading.
+    let title = html_escape(&url::percent_decode(target).unwrap_or_else(|| target.to_string()));
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html>\n    <head>\n        <meta charset=\"utf-8\">\n        <title>Index of {0}</title>\n        <style>td, th {{ padding: 0 1em 0 0; text-align: left; }} .size {{ text-align: right; }}</style>\n    </head>\n    <body>\n        <h1>Index of {0}</h1>\n        <table>\n            <tr><th><a href=\"?sort=name\">Name</a></th><th class=\"size\"><a href=\"?sort=size\">Size</a></th><th><a href=\"?sort=date\">Modified</a></th></tr>\n",
+        title
+    );
+    // Following a link into a directory, or up out of this one, keeps
+    // the order.
+    let keep_sort = match sort {
+        Sort::Name => String::new(),
+        sort => format!("?sort={}", sort.name()),
+    };
+    if target != "/" {
+        page.push_str(&format!(
+            "            <tr><td><a href=\"../{}\">../</a></td><td></td><td></td></tr>\n",
+            keep_sort
+        ));
+    }
+    for entry in &entries {
+        // A trailing / marks directories, and saves the client a
+        // redirect when they follow the link.
+        let (name, query, size) = if entry.dir {
+            (
+                format!("{}/", entry.name),
+                keep_sort.as_str(),
+                String::from("-"),
+            )
+        } else {
+            (entry.name.clone(), "", readable_size(entry.size))
+        };
+        // The link has to be percent-encoded or a name like
+        // "my file #1.txt" would end the URL at the #, and both end
+        // up in HTML so both are escaped for that too. File names can
+        // contain anything, including < and &.
+        let href = html_escape(&format!("{}{}", url::percent_encode_path(&name), query));
+        let modified = entry.modified.map(httpdate::format).unwrap_or_default();
+        page.push_str(&format!(
+            "            <tr><td><a href=\"{}\">{}</a></td><td class=\"size\">{}</td><td>{}</td></tr>\n",
+            href,
+            html_escape(&name),
+            size,
+            modified
+        ));
+    }
+    page.push_str("        </table>\n    </body>\n</html>\n");
+
+    Ok(Response::html(200, &page))
+}
+
+// A size the way people read them: 512 B, 3.4 KB, 12 MB. Kilobytes
+// here are 1024 bytes, like most file managers show.
+fn readable_size(size: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", size)
+    } else if value < 10.0 {
+        format!("{:.1} {}", value, UNITS[unit])
+    } else {
+        format!("{:.0} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::server::Server;
+    use crate::testing::{temp_dir, RawRequest};
+
+    // A listing server on a root of its own for test, holding
+    // "my file #1.txt" and a directory with a name as awkward.
+    fn server(test: &str) -> Server {
+        let root = temp_dir(test);
+        fs::write(root.join("my file #1.txt"), "one").unwrap();
+        fs::create_dir(root.join("my dir #2")).unwrap();
+        Server::builder()
+            .static_root(root)
+            .option("directory_listing", "true")
+            .build()
+            .unwrap()
+    }
+
+    fn get(server: &Server, path: &str) -> String {
+        let raw = RawRequest::new("GET", path).header("Host", "a").build();
+        String::from_utf8_lossy(&server.dispatch(&raw)).into_owned()
+    }
+
+    #[test]
+    fn links_in_a_listing_are_percent_encoded() {
+        let page = get(&server("listing-links"), "/");
+        assert!(page.contains("href=\"my%20file%20%231.txt\""), "{}", page);
+        assert!(page.contains("href=\"my%20dir%20%232/"), "{}", page);
+    }
+
+    #[test]
+    fn the_trailing_slash_redirect_is_percent_encoded() {
+        let response = get(&server("listing-redirect"), "/my%20dir%20%232");
+        assert!(response.starts_with("HTTP/1.1 301 "), "{}", response);
+        assert!(
+            response.contains("\r\nLocation: /my%20dir%20%232/\r\n"),
+            "{}",
+            response
+        );
+    }
+
+    #[test]
+    fn an_encoded_link_leads_to_the_file() {
+        let response = get(&server("listing-file"), "/my%20file%20%231.txt");
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with("\r\n\r\none"), "{}", response);
+    }
+}
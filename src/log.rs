@@ -0,0 +1,106 @@
+// Logging to stderr, filtered by level. The level is one setting for
+// the whole process, since there's one stderr to write to.
+//
+// The macros (error!, warn!, info!, debug!) check the level before
+// formatting anything, so a debug!() with the level at warn costs a
+// load and a comparison and nothing more.
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+use crate::httpdate;
+
+// From least to most chatty. Each level includes everything above
+// it, so info also logs warnings and errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    // Something failed: a handler panicked, a file couldn't be
+    // written.
+    Error,
+    // Something's probably wrong but the server carries on.
+    Warn,
+    // One line per response, like an access log.
+    Info,
+    // Everything: each connection, each request as it arrived, and
+    // why a connection was kept open or closed.
+    Debug,
+}
+
+impl Level {
+    // The level called name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "error" => Some(Level::Error),
+            "warn" | "warning" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+        }
+    }
+}
+
+static LEVEL: AtomicUsize = AtomicUsize::new(Level::Warn as usize);
+
+// Sets the most detailed level that gets logged.
+pub fn set_level(level: Level) {
+    LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+// Whether messages at level would be logged right now.
+pub fn enabled(level: Level) -> bool {
+    level as usize <= LEVEL.load(Ordering::Relaxed)
+}
+
+// Writes one line. Use the macros rather than calling this, so the
+// level is checked before the message is formatted.
+//
+// Every worker logs, so lines have to go out whole or they'd end up
+// in pieces between each other's. The line is put together first and
+// written with a single write_all() while holding stderr's lock: no
+// other thread can get a word in, and since stderr isn't buffered
+// that's one write() to the file or pipe, which another process
+// logging to the same place can't split either. eprintln!() holds
+// the lock too, but writes each piece of the line separately, and
+// panics if stderr has gone away, which would take a worker with it.
+pub fn write(level: Level, message: std::fmt::Arguments) {
+    let line = format!(
+        "{} {} {}\n",
+        httpdate::format(SystemTime::now()),
+        level.name(),
+        message
+    );
+    let _ = io::stderr().lock().write_all(line.as_bytes());
+}
+
+macro_rules! log_at {
+    ($level:expr, $($arg:tt)*) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write($level, format_args!($($arg)*));
+        }
+    };
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => { log_at!($crate::log::Level::Error, $($arg)*) };
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => { log_at!($crate::log::Level::Warn, $($arg)*) };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => { log_at!($crate::log::Level::Info, $($arg)*) };
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => { log_at!($crate::log::Level::Debug, $($arg)*) };
+}
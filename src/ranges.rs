@@ -0,0 +1,338 @@
+// Range requests, where the client asks for only part of a file:
+//
+//   Range: bytes=0-99        the first 100 bytes
+//   Range: bytes=500-        everything from byte 500 on
+//   Range: bytes=-100        the last 100 bytes
+//   Range: bytes=0-99,200-   more than one piece at once
+//
+// A single range comes back as a 206 with just those bytes. Several
+// come back as a multipart/byteranges body, each piece with its own
+// Content-Range. It's what lets a download resume, or a video player
+// skip ahead, without fetching the whole file again.
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::files;
+use crate::request::Request;
+use crate::response::{self, Body, Response};
+
+// More pieces than this in one request is almost certainly someone
+// trying to make us do a lot of work for a small request, so the
+// Range header is ignored and they get the whole file.
+const MAX_RANGES: usize = 16;
+
+// Makes multipart boundaries differ between responses.
+static BOUNDARY_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// What a Range header asks for, against a file of a given length.
+#[derive(Debug, PartialEq)]
+pub enum Ranges {
+    // The header isn't one we understand, so it's as if it wasn't
+    // there and the whole file is sent.
+    Ignore,
+    // None of the ranges overlap the file at all: a 416.
+    Unsatisfiable,
+    // The parts of the file to send, as start..end byte offsets.
+    Satisfiable(Vec<(usize, usize)>),
+}
+
+// Works out which bytes of a len byte file header asks for. Ranges
+// that start past the end of the file are dropped; ones that run
+// past it are cut short.
+pub fn parse(header: &str, len: usize) -> Ranges {
+    let specs = match header.trim().get(..6) {
+        Some(unit) if unit.eq_ignore_ascii_case("bytes=") => &header.trim()[6..],
+        _ => return Ranges::Ignore,
+    };
+
+    let specs: Vec<&str> = specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return Ranges::Ignore;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs {
+        let dash = match spec.find('-') {
+            Some(dash) => dash,
+            None => return Ranges::Ignore,
+        };
+        let (first, last) = (&spec[..dash], &spec[dash + 1..]);
+
+        if first.is_empty() {
+            // -n: the last n bytes.
+            let suffix = match number(last) {
+                Some(suffix) => suffix,
+                None => return Ranges::Ignore,
+            };
+            if suffix > 0 && len > 0 {
+                ranges.push((len - suffix.min(len), len));
+            }
+            continue;
+        }
+
+        let first = match number(first) {
+            Some(first) => first,
+            None => return Ranges::Ignore,
+        };
+        let last = if last.is_empty() {
+            None
+        } else {
+            match number(last) {
+                Some(last) if last >= first => Some(last),
+                _ => return Ranges::Ignore,
+            }
+        };
+        if first < len {
+            // last is inclusive, hence the + 1, which saturates since
+            // a client can send the biggest number there is.
+            let end = last.map_or(len, |last| last.saturating_add(1).min(len));
+            ranges.push((first, end));
+        }
+    }
+
+    if ranges.is_empty() {
+        return Ranges::Unsatisfiable;
+    }
+    // Overlapping ranges that add up to more than the file would make
+    // the response bigger than just sending the file.
+    let total: usize = ranges.iter().map(|(start, end)| end - start).sum();
+    if total > len {
+        return Ranges::Ignore;
+    }
+    Ranges::Satisfiable(ranges)
+}
+
+// Digits only. A big enough number to overflow couldn't be a place
+// in any file we could serve, so it's treated as invalid too.
+fn number(text: &str) -> Option<usize> {
+    if text.is_empty() || !text.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+// Cuts a 200 response for a file down to the ranges the request asks
+// for, if it asks for any. Anything else is left alone.
+pub fn apply(request: &Request, mut response: Response) -> Response {
+    if response.status != 200 || !(request.method == "GET" || request.method == "HEAD") {
+        return response;
+    }
+    if !matches!(response.body, Body::Bytes(_) | Body::Handle { .. }) {
+        return response;
+    }
+    response.set_header("Accept-Ranges", "bytes");
+
+    let header = match request.header("Range") {
+        Some(header) => header,
+        None => return response,
+    };
+    if !if_range_matches(request, &response) {
+        return response;
+    }
+
+    let len = match &response.body {
+        Body::Bytes(contents) => contents.len(),
+        Body::Handle { len, .. } => *len as usize,
+        _ => return response,
+    };
+    match parse(header, len) {
+        Ranges::Ignore => response,
+        Ranges::Unsatisfiable => {
+            let mut response = Response::new(416);
+            response.set_header("Content-Range", &format!("bytes */{}", len));
+            response
+        }
+        Ranges::Satisfiable(ranges) if ranges.len() == 1 => {
+            let (start, end) = ranges[0];
+            response.body = match &response.body {
+                // A file sent from disk just starts and stops somewhere
+                // else.
+                Body::Handle { file, offset, .. } => Body::Handle {
+                    file: Arc::clone(file),
+                    offset: offset + start as u64,
+                    len: (end - start) as u64,
+                },
+                _ => Body::Bytes(part(&response.body, start, end).unwrap_or_default()),
+            };
+            response.status = 206;
+            response.set_header(
+                "Content-Range",
+                &format!("bytes {}-{}/{}", start, end - 1, len),
+            );
+            response
+        }
+        // Several ranges of a file too big to keep in memory could add
+        // up to most of it, and the parts are put together in memory,
+        // so past the same size the whole file is sent instead, which
+        // clients asking for ranges have to accept.
+        Ranges::Satisfiable(ranges)
+            if matches!(response.body, Body::Handle { .. })
+                && ranges.iter().map(|(start, end)| end - start).sum::<usize>()
+                    >= files::SEND_FROM_DISK as usize =>
+        {
+            response
+        }
+        Ranges::Satisfiable(ranges) => {
+            let boundary = format!(
+                "byteranges-{:x}-{:x}",
+                std::process::id(),
+                BOUNDARY_COUNTER.fetch_add(1, Ordering::Relaxed)
+            );
+            let content_type = response.header("Content-Type").map(str::to_string);
+
+            let mut body = Vec::new();
+            for (start, end) in ranges {
+                body.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+                if let Some(content_type) = &content_type {
+                    body.extend_from_slice(
+                        format!("Content-Type: {}\r\n", content_type).as_bytes(),
+                    );
+                }
+                body.extend_from_slice(
+                    format!("Content-Range: bytes {}-{}/{}\r\n\r\n", start, end - 1, len)
+                        .as_bytes(),
+                );
+                match part(&response.body, start, end) {
+                    Ok(part) => body.extend_from_slice(&part),
+                    // The file couldn't be read after all, so the
+                    // ranges can't be either.
+                    Err(_) => return response,
+                }
+                body.extend_from_slice(b"\r\n");
+            }
+            body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+
+            response.status = 206;
+            response.set_header(
+                "Content-Type",
+                &format!("multipart/byteranges; boundary={}", boundary),
+            );
+            response.body = Body::Bytes(body);
+            response
+        }
+    }
+}
+
+// The bytes from start to end of a file body.
+fn part(body: &Body, start: usize, end: usize) -> io::Result<Vec<u8>> {
+    match body {
+        Body::Bytes(contents) => Ok(contents[start..end].to_vec()),
+        Body::Handle { file, offset, .. } => {
+            let mut part = Vec::with_capacity(end - start);
+            response::copy_file(file, offset + start as u64, (end - start) as u64, &mut part)?;
+            Ok(part)
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
+// If-Range says "send the ranges if the file is still this version,
+// otherwise send me all of it", so a resumed download can't end up
+// stitched together from two different files. It holds either an
+// ETag, which has to match exactly (weak ones never do), or a date,
+// which has to be the file's Last-Modified.
+fn if_range_matches(request: &Request, response: &Response) -> bool {
+    let condition = match request.header("If-Range") {
+        Some(condition) => condition.trim(),
+        None => return true,
+    };
+    if condition.starts_with('"') {
+        return response.header("ETag") == Some(condition);
+    }
+    if condition.starts_with("W/") {
+        return false;
+    }
+    response.header("Last-Modified") == Some(condition)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &[u8] = b"0123456789";
+
+    // The response to a GET of FILE with range as its Range header.
+    fn get(range: &str) -> Response {
+        let raw = format!("GET /file HTTP/1.1\r\nHost: a\r\nRange: {}\r\n\r\n", range);
+        let request = Request::parse(raw.as_bytes()).unwrap();
+        apply(&request, Response::bytes(200, "text/plain", FILE.to_vec()))
+    }
+
+    fn body(response: &Response) -> &[u8] {
+        match &response.body {
+            Body::Bytes(bytes) => bytes,
+            body => panic!("not bytes: {:?}", body),
+        }
+    }
+
+    #[test]
+    fn a_single_range() {
+        assert_eq!(parse("bytes=2-4", 10), Ranges::Satisfiable(vec![(2, 5)]));
+        let response = get("bytes=2-4");
+        assert_eq!(response.status, 206);
+        assert_eq!(response.header("Content-Range"), Some("bytes 2-4/10"));
+        assert_eq!(body(&response), b"234");
+    }
+
+    #[test]
+    fn a_suffix_range() {
+        assert_eq!(parse("bytes=-3", 10), Ranges::Satisfiable(vec![(7, 10)]));
+        assert_eq!(parse("bytes=-30", 10), Ranges::Satisfiable(vec![(0, 10)]));
+        assert_eq!(body(&get("bytes=-3")), b"789");
+    }
+
+    #[test]
+    fn an_open_ended_range() {
+        assert_eq!(parse("bytes=6-", 10), Ranges::Satisfiable(vec![(6, 10)]));
+        assert_eq!(parse("bytes=6-100", 10), Ranges::Satisfiable(vec![(6, 10)]));
+        assert_eq!(body(&get("bytes=6-")), b"6789");
+    }
+
+    #[test]
+    fn ranges_adding_up_to_more_than_the_file_get_all_of_it() {
+        assert_eq!(parse("bytes=0-7,2-9", 10), Ranges::Ignore);
+        let response = get("bytes=0-7,2-9");
+        assert_eq!(response.status, 200);
+        assert_eq!(body(&response), FILE);
+    }
+
+    #[test]
+    fn a_range_past_the_end_is_unsatisfiable() {
+        assert_eq!(parse("bytes=10-", 10), Ranges::Unsatisfiable);
+        let response = get("bytes=10-20");
+        assert_eq!(response.status, 416);
+        assert_eq!(response.header("Content-Range"), Some("bytes */10"));
+    }
+
+    #[test]
+    fn huge_numbers_dont_overflow() {
+        let biggest = format!("bytes=0-{}", usize::MAX);
+        assert_eq!(parse(&biggest, 10), Ranges::Satisfiable(vec![(0, 10)]));
+        assert_eq!(get(&biggest).header("Content-Range"), Some("bytes 0-9/10"));
+        // Too big for a usize at all isn't a range we understand.
+        assert_eq!(parse("bytes=0-99999999999999999999999", 10), Ranges::Ignore);
+        assert_eq!(parse("bytes=99999999999999999999999-", 10), Ranges::Ignore);
+    }
+
+    #[test]
+    fn several_ranges_come_back_as_multipart() {
+        let response = get("bytes=0-1,5-6");
+        assert_eq!(response.status, 206);
+        let content_type = response.header("Content-Type").unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+        let expected = format!(
+            "--{0}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n01\r\n\
+             --{0}\r\nContent-Type: text/plain\r\nContent-Range: bytes 5-6/10\r\n\r\n56\r\n\
+             --{0}--\r\n",
+            boundary
+        );
+        assert_eq!(String::from_utf8_lossy(body(&response)), expected);
+    }
+}
@@ -0,0 +1,847 @@
+// Read is the trait that gives streams their read() method.
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::cookie;
+use crate::forwarded::Client;
+use crate::session::Session;
+
+// A parsed HTTP request. Everything is kept as the client sent
+// it (the path is still percent-encoded, header names keep their
+// case) so handlers see exactly what came over the wire.
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    // The part of the target before the ?.
+    pub path: String,
+    // The part of the target after the ?, if there was one.
+    pub query: Option<String>,
+    pub version: String,
+    // Headers in the order they arrived. It's a Vec rather than
+    // a map because a header is allowed to show up more than once.
+    pub headers: Vec<(String, String)>,
+    // The cookies from the Cookie header, by name.
+    pub cookies: HashMap<String, String>,
+    // Whatever came after the headers, as Content-Length said or
+    // put back together from chunks. Empty for requests without a
+    // body, and for ones whose handler reads the body itself as it
+    // arrives (see Server::route_streaming()).
+    pub body: Vec<u8>,
+    // Headers sent after a chunked body. Empty unless there was one
+    // and it came with trailers. A streamed body's trailers are on
+    // its BodyReader instead.
+    pub trailers: Vec<(String, String)>,
+    // When the server stops waiting for this request's response, see
+    // deadline().
+    pub(crate) deadline: Instant,
+    // Who sent it, see client().
+    pub(crate) client: Client,
+    // Who they logged in as, see identity().
+    pub(crate) identity: Option<String>,
+    // See session().
+    pub(crate) session: Option<Session>,
+}
+
+// The deadline of a request that didn't come through the server,
+// which never sets a shorter one.
+const NO_DEADLINE: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+impl Request {
+    // Parses the request line and headers out of raw bytes:
+    //
+    // Method Request-URI HTTP-Version CRLF
+    // Name: value CRLF
+    // ...
+    // CRLF
+    //
+    // Fails with ParseError::Malformed if the request line doesn't
+    // have all three parts, or a header line has no colon or no name
+    // before it, and with ParseError::Version for an HTTP version
+    // other than 1.0 and 1.1.
+    pub fn parse(raw: &[u8]) -> Result<Request, ParseError> {
+        let text = String::from_utf8_lossy(raw);
+        let mut lines = text.split("\r\n");
+
+        let mut parts = lines.next().ok_or(ParseError::Malformed)?.split(' ');
+        let method = parts.next().ok_or(ParseError::Malformed)?.to_string();
+        let target = parts.next().ok_or(ParseError::Malformed)?;
+        let version = parts.next().ok_or(ParseError::Malformed)?.to_string();
+        if method.is_empty() || parts.next().is_some() || !valid_target(target) {
+            return Err(ParseError::Malformed);
+        }
+        check_version(&version)?;
+
+        let (path, query) = match target.find('?') {
+            Some(i) => (&target[..i], Some(target[i + 1..].to_string())),
+            None => (target, None),
+        };
+
+        let mut headers = Vec::new();
+        for line in lines {
+            // The empty line marks the end of the headers.
+            if line.is_empty() {
+                break;
+            }
+            let colon = line.find(':').ok_or(ParseError::Malformed)?;
+            // A field name is never empty and has no whitespace in
+            // it. "Content-Length : 5" in particular has to be a 400
+            // (RFC 9112 section 5.1), since proxies don't agree on
+            // whether it's a Content-Length, and disagreeing about
+            // where a body ends is how requests get smuggled.
+            let name = &line[..colon];
+            if name.is_empty() || name.bytes().any(|b| b.is_ascii_whitespace()) {
+                return Err(ParseError::Malformed);
+            }
+            headers.push((name.to_string(), line[colon + 1..].trim().to_string()));
+        }
+
+        let client = Client {
+            addr: None,
+            scheme: String::from("http"),
+            host: headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("Host"))
+                .map(|(_, value)| value.clone()),
+        };
+
+        Ok(Request {
+            method,
+            path: path.to_string(),
+            query,
+            version,
+            cookies: cookie::parse(&headers),
+            headers,
+            body: Vec::new(),
+            trailers: Vec::new(),
+            deadline: Instant::now() + NO_DEADLINE,
+            client,
+            identity: None,
+            session: None,
+        })
+    }
+
+    // The request target as it appeared in the request line.
+    pub fn target(&self) -> String {
+        match &self.query {
+            Some(query) => format!("{}?{}", self.path, query),
+            None => self.path.clone(),
+        }
+    }
+
+    // When the server gives up on this request: Config::
+    // max_request_duration after its head arrived. A handler doing a
+    // lot of work can check it and stop early, since once it's
+    // passed nobody will see the response anyway.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    // Whether the deadline has passed.
+    pub fn expired(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+
+    // The client that sent this request: its address, whether it
+    // used http or https, and the host it asked for. Behind a proxy
+    // listed in Config::trusted_proxies these come from the
+    // X-Forwarded-* headers it adds; otherwise they're what the
+    // connection itself shows, and those headers are ignored. Log
+    // this rather than the peer, and key anything per-client on it.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    // Who the request is from, as the authenticator that let it
+    // through a protected path told us (see Config::protected). None
+    // on paths that aren't protected, and when the authenticator
+    // didn't say.
+    pub fn identity(&self) -> Option<&str> {
+        self.identity.as_deref()
+    }
+
+    // The session this request belongs to, for a route handler to
+    // keep things in between requests (see session.rs). None unless
+    // Config::sessions or csrf_protection is on, and for static
+    // files, which never need one.
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    // The value of the first header with this name. Header names
+    // are case-insensitive, so Host and host are the same header.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// Whether a request target is one we can make sense of: a path
+// starting with /, or the * that OPTIONS uses for the whole server.
+// An empty one is what "GET  HTTP/1.1" leaves, with the path
+// missing from between the spaces. Control characters can't appear
+// in a URL unescaped, and one that does is more likely an attempt to
+// sneak something into our logs than a real request.
+fn valid_target(target: &str) -> bool {
+    (target == "*" || target.starts_with('/')) && !target.chars().any(char::is_control)
+}
+
+// What's wrong with a request head that Request::parse() turned down.
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    // It isn't a request line and headers at all, which is a 400.
+    Malformed,
+    // It's HTTP, but a version other than 1.0 or 1.1, which is a 505.
+    Version,
+}
+
+// An HTTP version is HTTP/ and then a digit, a dot and a digit (RFC
+// 9112 section 2.3). Anything else isn't one, and of the ones that
+// are only 1.0 and 1.1 are spoken here.
+fn check_version(version: &str) -> Result<(), ParseError> {
+    let digits = match version.strip_prefix("HTTP/").map(str::as_bytes) {
+        Some([major, b'.', minor]) if major.is_ascii_digit() && minor.is_ascii_digit() => {
+            (major, minor)
+        }
+        _ => return Err(ParseError::Malformed),
+    };
+    match digits {
+        (b'1', b'0') | (b'1', b'1') => Ok(()),
+        _ => Err(ParseError::Version),
+    }
+}
+
+// Why reading the head of a request stopped.
+#[derive(Debug)]
+pub enum HeadError {
+    // The client closed the connection, or reading from it failed.
+    Io(io::Error),
+    // The request line went past Config::max_request_line.
+    RequestLineTooLong,
+    // The head went past Config::max_header_size.
+    HeaderTooLarge,
+    // What came in is the start of a TLS handshake, from a client
+    // that thinks it's talking to an HTTPS port.
+    TlsHandshake,
+}
+
+// Reads from stream until buffer holds a whole request line and
+// headers, ending with the empty line. Returns how many bytes of
+// buffer that head takes up; anything after it is the start of the
+// body.
+//
+// A request can arrive in as many pieces as the network likes, so we
+// keep reading until we see the blank line rather than trusting a
+// single read() to have it all. The pieces can split anywhere, even
+// in the middle of the \r\n\r\n, and it makes no difference: we
+// always look through everything read so far, never just the last
+// piece on its own.
+pub fn read_head<R: Read>(
+    stream: &mut R,
+    buffer: &mut Vec<u8>,
+    config: &Config,
+) -> Result<usize, HeadError> {
+    // Config::read_buffer_size at a time is enough for most requests
+    // in one go, and bigger ones just take a few more reads. It's only
+    // made when something has to be read, since a pipelined request
+    // is often in the buffer already.
+    let mut chunk = Vec::new();
+
+    // How far the searches below have already looked. The next search
+    // starts a few bytes before that, in case the last read stopped
+    // partway through what we're looking for, instead of going over
+    // the whole buffer again. With a client sending a byte at a time,
+    // starting from the top every time would be quadratic in the size
+    // of the head.
+    let mut searched = 0;
+    let mut line_end = None;
+    let mut blank_lines = 0;
+
+    loop {
+        if looks_like_tls(buffer) {
+            return Err(HeadError::TlsHandshake);
+        }
+        // Some clients send a CRLF or two ahead of the request line,
+        // most often after the body of a POST, and RFC 9112 section
+        // 2.2 says to skip them. Only a few are, so a client can't
+        // keep us busy sending nothing else; past that the request is
+        // a bad one.
+        while blank_lines < MAX_BLANK_LINES && buffer.starts_with(b"\r\n") {
+            buffer.drain(..2);
+            blank_lines += 1;
+            searched = 0;
+            line_end = None;
+        }
+        if let Some(end) = find_from(buffer, b"\r\n\r\n", searched) {
+            let head = end + 4;
+            if head > config.max_header_size {
+                return Err(HeadError::HeaderTooLarge);
+            }
+            return Ok(head);
+        }
+        if line_end.is_none() {
+            line_end = find_from(buffer, b"\r\n", searched);
+        }
+        searched = buffer.len().saturating_sub(3);
+
+        // Check the limits before reading more, so a client can't
+        // make us buffer forever by never sending the blank line.
+        match line_end {
+            None if buffer.len() > config.max_request_line => {
+                return Err(HeadError::RequestLineTooLong);
+            }
+            Some(line) if line > config.max_request_line => {
+                return Err(HeadError::RequestLineTooLong);
+            }
+            _ if buffer.len() > config.max_header_size => {
+                return Err(HeadError::HeaderTooLarge);
+            }
+            _ => {}
+        }
+
+        chunk.resize(config.read_buffer_size, 0);
+        let read = stream.read(&mut chunk).map_err(HeadError::Io)?;
+        if read == 0 {
+            return Err(HeadError::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+}
+
+// How many empty lines read_head() skips before a request line.
+pub(crate) const MAX_BLANK_LINES: usize = 4;
+
+// Whether buffer starts the way a TLS handshake does: a handshake
+// record (22) and a major version of 3, which every version of TLS
+// and SSL 3 have used. No request line can start with either byte.
+// The client is waiting for us to answer the handshake and would
+// never send a blank line, so without this both sides would wait
+// for each other until the read timeout.
+fn looks_like_tls(buffer: &[u8]) -> bool {
+    buffer.starts_with(&[0x16, 0x03])
+}
+
+// Where needle first shows up in haystack.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+// find(), skipping the first start bytes of haystack.
+fn find_from(haystack: &[u8], needle: &[u8], start: usize) -> Option<usize> {
+    find(haystack.get(start..)?, needle).map(|i| start + i)
+}
+
+// Why a request's body couldn't be read.
+#[derive(Debug)]
+pub enum BodyError {
+    // The client closed the connection, or reading from it failed.
+    Io(io::Error),
+    // The body is bigger than Config::max_body_size.
+    TooLarge,
+    // Content-Length isn't a number, there are several that
+    // disagree, or the chunked encoding is broken.
+    Invalid,
+    // The body uses a Transfer-Encoding we can't decode.
+    Unsupported,
+    // There's a body, but the method isn't in Config::body_methods
+    // and Config::reject_unexpected_body is on.
+    Unexpected,
+}
+
+// Fields that can't be trusted in a trailer, because they'd change
+// how the message is framed, routed or authorized after we've already
+// acted on the headers. RFC 9110 section 6.5.1 has the reasoning.
+// Trailers with these names are dropped.
+const DISALLOWED_TRAILERS: [&str; 16] = [
+    "Authorization",
+    "Cache-Control",
+    "Content-Encoding",
+    "Content-Length",
+    "Content-Range",
+    "Content-Type",
+    "Cookie",
+    "Expect",
+    "Host",
+    "Max-Forwards",
+    "Pragma",
+    "Proxy-Authorization",
+    "Range",
+    "Set-Cookie",
+    "TE",
+    "Trailer",
+];
+
+// How the end of a request's body is found.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Framing {
+    // The body is exactly this many bytes.
+    Length(usize),
+    // Transfer-Encoding: chunked, a series of chunks ending in an
+    // empty one.
+    Chunked,
+}
+
+// Works out from its headers how request's body is framed.
+pub fn framing(request: &Request) -> Result<Framing, BodyError> {
+    if let Some(coding) = request.header("Transfer-Encoding") {
+        // Only the one header is looked at here, but a proxy in front
+        // might go by the last of several, or join them up, and frame
+        // the request some other way. A list of codings is the same
+        // thing in one header. Chunked on its own is all we decode
+        // anyway, so anything more is refused.
+        let headers = request
+            .headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("Transfer-Encoding"))
+            .count();
+        if headers > 1 || coding.contains(',') {
+            return Err(BodyError::Invalid);
+        }
+        if !coding.trim().eq_ignore_ascii_case("chunked") {
+            return Err(BodyError::Unsupported);
+        }
+        // A message with both is framed one way by some servers and
+        // the other way by others, which is exactly what request
+        // smuggling needs, so refuse it outright.
+        if request.header("Content-Length").is_some() {
+            return Err(BodyError::Invalid);
+        }
+        return Ok(Framing::Chunked);
+    }
+
+    // Two Content-Lengths that disagree are a classic way to get a
+    // proxy and a server to split requests differently (request
+    // smuggling), so that's an error rather than a guess.
+    let mut length = None;
+    for (name, value) in &request.headers {
+        if !name.eq_ignore_ascii_case("Content-Length") {
+            continue;
+        }
+        let value = value.trim();
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(BodyError::Invalid);
+        }
+        let value: usize = value.parse().map_err(|_| BodyError::TooLarge)?;
+        if length.is_some_and(|length| length != value) {
+            return Err(BodyError::Invalid);
+        }
+        length = Some(value);
+    }
+
+    Ok(Framing::Length(length.unwrap_or(0)))
+}
+
+// Reads the body of request into request.body, starting at the front
+// of buffer (where read_head() left whatever followed the headers)
+// and carrying on into the stream. Anything in buffer past the body
+// is left there, since it's the next request.
+pub fn read_body<R: Read>(
+    stream: &mut R,
+    buffer: &mut Vec<u8>,
+    request: &mut Request,
+    config: &Config,
+) -> Result<(), BodyError> {
+    let framing = framing(request)?;
+    let expected = takes_body(config, request);
+    if !expected && framing != Framing::Length(0) && config.reject_unexpected_body {
+        return Err(BodyError::Unexpected);
+    }
+
+    match framing {
+        Framing::Chunked => read_chunked(stream, buffer, request, config)?,
+        Framing::Length(length) => {
+            if length > config.max_body_size {
+                return Err(BodyError::TooLarge);
+            }
+            request.body = take(stream, buffer, length)?;
+        }
+    }
+
+    // A body nobody asked for is read all the same, since leaving it
+    // in the stream would have it taken for the next request.
+    if !expected {
+        request.body.clear();
+        request.trailers.clear();
+    }
+    Ok(())
+}
+
+// Whether request's method is one of Config::body_methods.
+pub fn takes_body(config: &Config, request: &Request) -> bool {
+    config.body_methods.contains(&request.method)
+}
+
+// A chunked body looks like this, with each chunk's size in hex:
+//
+// 5 CRLF
+// hello CRLF
+// 0 CRLF
+// Name: value CRLF    <- trailers, if any
+// CRLF
+//
+// The trailers are headers the client only knew once it had sent the
+// body, like a checksum of it. They go in request.trailers, minus any
+// in DISALLOWED_TRAILERS.
+fn read_chunked<R: Read>(
+    stream: &mut R,
+    buffer: &mut Vec<u8>,
+    request: &mut Request,
+    config: &Config,
+) -> Result<(), BodyError> {
+    let mut body = Vec::new();
+    loop {
+        let size = chunk_size(stream, buffer, config)?;
+        if size == 0 {
+            break;
+        }
+        if size > config.max_body_size - body.len() {
+            return Err(BodyError::TooLarge);
+        }
+
+        body.extend(take(stream, buffer, size)?);
+        if take(stream, buffer, 2)? != b"\r\n" {
+            return Err(BodyError::Invalid);
+        }
+    }
+
+    request.trailers = read_trailers(stream, buffer, config)?;
+    request.body = body;
+    Ok(())
+}
+
+// Reads the line that starts a chunk, and returns the size on it.
+fn chunk_size<R: Read + ?Sized>(
+    stream: &mut R,
+    buffer: &mut Vec<u8>,
+    config: &Config,
+) -> Result<usize, BodyError> {
+    let line = read_line(stream, buffer, config.max_request_line, config)?;
+    // The size can be followed by ;name=value extensions, which
+    // nothing uses and we ignore.
+    let size = line.split(';').next().unwrap_or("").trim();
+    if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(BodyError::Invalid);
+    }
+    usize::from_str_radix(size, 16).map_err(|_| BodyError::TooLarge)
+}
+
+// Reads the trailers after the last chunk, up to the blank line.
+fn read_trailers<R: Read + ?Sized>(
+    stream: &mut R,
+    buffer: &mut Vec<u8>,
+    config: &Config,
+) -> Result<Vec<(String, String)>, BodyError> {
+    // Trailers get the same room as the headers did, so a client
+    // can't make us buffer forever by never sending the blank line.
+    let mut room = config.max_header_size;
+    let mut trailers = Vec::new();
+    loop {
+        let line = read_line(stream, buffer, room, config)?;
+        if line.is_empty() {
+            return Ok(trailers);
+        }
+        room = room.saturating_sub(line.len() + 2);
+
+        // A line without a colon can't be a field; skipping it still
+        // leaves us in step with the stream.
+        if let Some(colon) = line.find(':') {
+            let name = line[..colon].trim();
+            let disallowed = DISALLOWED_TRAILERS
+                .iter()
+                .any(|field| field.eq_ignore_ascii_case(name))
+                || name.to_ascii_lowercase().starts_with("if-");
+            if !disallowed {
+                trailers.push((name.to_string(), line[colon + 1..].trim().to_string()));
+            }
+        }
+    }
+}
+
+// A request body read as it arrives rather than all at once, for
+// handlers that would rather not hold a big upload in memory (see
+// Server::route_streaming()). It's a Read, so io::copy() and friends
+// work on it, and it ends where the body does, whichever way it's
+// framed.
+//
+// Nothing caps how much of a body can be read this way, since none of
+// it is kept unless the handler keeps it. max_body_size doesn't
+// apply; a handler that wants a limit can take() one.
+pub struct BodyReader<'a> {
+    stream: &'a mut dyn Read,
+    // What's been read from the stream but not handed out yet. It's
+    // the connection's buffer, so whatever follows the body stays in
+    // it for the next request.
+    buffer: &'a mut Vec<u8>,
+    framing: Framing,
+    config: &'a Config,
+    // Bytes left in the body, or in the current chunk.
+    remaining: usize,
+    // Whether a chunk has been started, so the CRLF after it is due.
+    in_chunk: bool,
+    finished: bool,
+    trailers: Vec<(String, String)>,
+}
+
+impl<'a> BodyReader<'a> {
+    pub fn new(
+        stream: &'a mut dyn Read,
+        buffer: &'a mut Vec<u8>,
+        framing: Framing,
+        config: &'a Config,
+    ) -> BodyReader<'a> {
+        let remaining = match framing {
+            Framing::Length(length) => length,
+            Framing::Chunked => 0,
+        };
+        BodyReader {
+            stream,
+            buffer,
+            framing,
+            config,
+            remaining,
+            in_chunk: false,
+            finished: framing == Framing::Length(0),
+            trailers: Vec::new(),
+        }
+    }
+
+    // Whether the whole body has been read. Until it has, the rest of
+    // it is still in the way of the next request.
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    // The trailers of a chunked body, once it's been read to the end.
+    pub fn trailers(&self) -> &[(String, String)] {
+        &self.trailers
+    }
+
+    // Gets remaining set up for the next chunk of a chunked body.
+    // Returns false if that was the last one.
+    fn next_chunk(&mut self) -> Result<bool, BodyError> {
+        if self.in_chunk && take(self.stream, self.buffer, 2)? != b"\r\n" {
+            return Err(BodyError::Invalid);
+        }
+        self.remaining = chunk_size(self.stream, self.buffer, self.config)?;
+        self.in_chunk = true;
+        if self.remaining == 0 {
+            self.trailers = read_trailers(self.stream, self.buffer, self.config)?;
+            return Ok(false);
+        }
+        Ok(true)
+    }
+}
+
+impl Read for BodyReader<'_> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.finished || out.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            let more = self.framing == Framing::Chunked && self.next_chunk()?;
+            if !more {
+                self.finished = true;
+                return Ok(0);
+            }
+        }
+
+        let wanted = out.len().min(self.remaining);
+        let read = if self.buffer.is_empty() {
+            let read = self.stream.read(&mut out[..wanted])?;
+            if read == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            read
+        } else {
+            let read = wanted.min(self.buffer.len());
+            out[..read].copy_from_slice(&self.buffer[..read]);
+            self.buffer.drain(..read);
+            read
+        };
+        self.remaining -= read;
+        if self.remaining == 0 && self.framing != Framing::Chunked {
+            self.finished = true;
+        }
+        Ok(read)
+    }
+}
+
+impl From<BodyError> for io::Error {
+    fn from(error: BodyError) -> io::Error {
+        match error {
+            BodyError::Io(e) => e,
+            BodyError::TooLarge => io::Error::new(io::ErrorKind::InvalidData, "chunk too large"),
+            BodyError::Invalid => io::Error::new(io::ErrorKind::InvalidData, "malformed body"),
+            BodyError::Unsupported => {
+                io::Error::new(io::ErrorKind::InvalidData, "unsupported transfer encoding")
+            }
+            BodyError::Unexpected => io::Error::new(
+                io::ErrorKind::InvalidData,
+                "body on a method that can't have one",
+            ),
+        }
+    }
+}
+
+// Reads one CRLF-terminated line, without the CRLF. Fails if it's
+// longer than limit.
+fn read_line<R: Read + ?Sized>(
+    stream: &mut R,
+    buffer: &mut Vec<u8>,
+    limit: usize,
+    config: &Config,
+) -> Result<String, BodyError> {
+    let mut chunk = Vec::new();
+    loop {
+        if let Some(end) = find(buffer, b"\r\n") {
+            if end > limit {
+                return Err(BodyError::Invalid);
+            }
+            let line: Vec<u8> = buffer.drain(..end + 2).take(end).collect();
+            return Ok(String::from_utf8_lossy(&line).into_owned());
+        }
+        if buffer.len() > limit {
+            return Err(BodyError::Invalid);
+        }
+
+        chunk.resize(config.read_buffer_size, 0);
+        let read = stream.read(&mut chunk).map_err(BodyError::Io)?;
+        if read == 0 {
+            return Err(BodyError::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+}
+
+// Takes the next length bytes, from the front of buffer first and
+// then from the stream.
+fn take<R: Read + ?Sized>(
+    stream: &mut R,
+    buffer: &mut Vec<u8>,
+    length: usize,
+) -> Result<Vec<u8>, BodyError> {
+    let buffered = length.min(buffer.len());
+    let mut bytes: Vec<u8> = buffer.drain(..buffered).collect();
+    if bytes.len() < length {
+        let start = bytes.len();
+        bytes.resize(length, 0);
+        stream
+            .read_exact(&mut bytes[start..])
+            .map_err(BodyError::Io)?;
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A stream that hands over one byte per read(), the most a
+    // request can be split up.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match (self.0.split_first(), buf.first_mut()) {
+                (Some((&byte, rest)), Some(slot)) => {
+                    *slot = byte;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn a_head_read_a_byte_at_a_time_parses() {
+        let raw =
+            b"POST /form HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhelloGET / HTTP/1.1\r\n";
+        let config = Config::default();
+        let mut stream = Trickle(raw);
+        let mut buffer = Vec::new();
+
+        let head = read_head(&mut stream, &mut buffer, &config).unwrap();
+        let mut request = Request::parse(&buffer[..head]).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.header("Content-Length"), Some("5"));
+        buffer.drain(..head);
+
+        read_body(&mut stream, &mut buffer, &mut request, &config).unwrap();
+        assert_eq!(request.body, b"hello");
+        // Whatever came after the body is the next request, untouched.
+        let mut rest = buffer;
+        stream.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+    }
+
+    // How the body of a POST with headers is framed.
+    fn framing_of(headers: &str) -> Result<Framing, BodyError> {
+        let raw = format!("POST / HTTP/1.1\r\nHost: a\r\n{}\r\n", headers);
+        framing(&Request::parse(raw.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn chunked_is_the_only_transfer_encoding() {
+        let chunked = framing_of("Transfer-Encoding: chunked\r\n");
+        assert!(matches!(chunked, Ok(Framing::Chunked)));
+        let gzip = framing_of("Transfer-Encoding: gzip\r\n");
+        assert!(matches!(gzip, Err(BodyError::Unsupported)));
+    }
+
+    #[test]
+    fn transfer_encodings_that_could_be_read_two_ways_are_refused() {
+        for headers in [
+            "Transfer-Encoding: chunked\r\nTransfer-Encoding: identity\r\n",
+            "Transfer-Encoding: chunked\r\nTransfer-Encoding: chunked\r\n",
+            "Transfer-Encoding: chunked, identity\r\n",
+            "Transfer-Encoding: gzip, chunked\r\n",
+            "Transfer-Encoding: chunked\r\nContent-Length: 5\r\n",
+        ] {
+            assert!(
+                matches!(framing_of(headers), Err(BodyError::Invalid)),
+                "{:?}",
+                headers
+            );
+        }
+    }
+
+    #[test]
+    fn whitespace_around_a_header_name_is_malformed() {
+        let parse = |header: &str| {
+            Request::parse(format!("GET / HTTP/1.1\r\n{}\r\n\r\n", header).as_bytes())
+        };
+        let request = parse("Content-Length:  5 ").unwrap();
+        assert_eq!(request.header("Content-Length"), Some("5"));
+        for header in [
+            "Content-Length : 5",
+            "Transfer-Encoding\t: chunked",
+            " Host: a",
+            ": no name",
+        ] {
+            assert_eq!(
+                parse(header).unwrap_err(),
+                ParseError::Malformed,
+                "{:?}",
+                header
+            );
+        }
+    }
+
+    #[test]
+    fn only_http_1_0_and_1_1_parse() {
+        let parse = |line: &str| Request::parse(format!("{}\r\nHost: a\r\n\r\n", line).as_bytes());
+        assert_eq!(parse("GET / HTTP/1.0").unwrap().version, "HTTP/1.0");
+        assert_eq!(parse("GET / HTTP/1.1").unwrap().version, "HTTP/1.1");
+        assert_eq!(parse("GET / HTTP/2.0").unwrap_err(), ParseError::Version);
+        assert_eq!(parse("GET / HTTP/0.9").unwrap_err(), ParseError::Version);
+        assert_eq!(parse("GET / FOO").unwrap_err(), ParseError::Malformed);
+        assert_eq!(parse("GET / HTTP/1.10").unwrap_err(), ParseError::Malformed);
+        assert_eq!(parse("GET / http/1.1").unwrap_err(), ParseError::Malformed);
+    }
+}
@@ -0,0 +1,265 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+
+use crate::connection::Connection;
+use crate::cookie::Cookie;
+
+// Big enough that the head and body of a typical page fit in one
+// write. Larger bodies skip the buffer and are written directly.
+// Config::write_buffer_size can change it for connections.
+pub(crate) const WRITE_BUFFER: usize = 8 * 1024;
+
+// What gets sent after the headers.
+#[derive(Debug, Clone)]
+pub enum Body {
+    Empty,
+    Text(String),
+    // Raw bytes, like the contents of a file we've read.
+    Bytes(Vec<u8>),
+    // A file under the static root that hasn't been read yet.
+    // The server swaps this for the file's contents (with all the
+    // usual content type and caching headers) before sending, so
+    // handlers can pick a file without reading it themselves.
+    File(String),
+    // len bytes of an open file, starting at offset. Files too big
+    // to want in memory are sent like this, straight from the disk.
+    Handle {
+        file: Arc<File>,
+        offset: u64,
+        len: u64,
+    },
+}
+
+// A response waiting to be written to the stream.
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub(crate) body: Body,
+    // Set for the answer to a HEAD request: the head goes out just as
+    // it would for a GET, Content-Length and all, but not the body.
+    pub(crate) head_only: bool,
+}
+
+impl Response {
+    // An empty response with the given status.
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Body::Empty,
+            head_only: false,
+        }
+    }
+
+    // A plain text response.
+    pub fn text(status: u16, body: &str) -> Response {
+        let mut response = Response::new(status);
+        response.set_header("Content-Type", "text/plain; charset=utf-8");
+        response.body = Body::Text(body.to_string());
+        response
+    }
+
+    // An HTML response.
+    pub fn html(status: u16, body: &str) -> Response {
+        let mut response = Response::new(status);
+        response.set_header("Content-Type", "text/html; charset=utf-8");
+        response.body = Body::Text(body.to_string());
+        response
+    }
+
+    // A response of bytes made in memory, like a rendered image or a
+    // zip, sent as content_type. Content-Length is worked out from
+    // body when it's written, like every other body.
+    pub fn bytes(status: u16, content_type: &str, body: Vec<u8>) -> Response {
+        let mut response = Response::new(status);
+        response.set_header("Content-Type", content_type);
+        response.body = Body::Bytes(body);
+        response
+    }
+
+    // Sends the client to location instead. 301 is for moves that
+    // are for good, 302 for ones that aren't.
+    pub fn redirect(status: u16, location: &str) -> Response {
+        let mut response = Response::new(status);
+        response.set_header("Location", location);
+        response
+    }
+
+    // Serves the file at path, which is a URL path under the
+    // static root (/img/logo.png, not html/img/logo.png). It goes
+    // through the same checks as a request for that path, so a
+    // handler can't be tricked into serving ../../etc/passwd.
+    pub fn file(path: &str) -> Response {
+        let mut response = Response::new(200);
+        response.body = Body::File(path.to_string());
+        response
+    }
+
+    // The value of the first header with this name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Sets a header, replacing any earlier value for it.
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        self.headers
+            .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    // Whether a response with this status can have a body at all.
+    // 1xx, 204 No Content and 304 Not Modified never do, so for those
+    // there's no Content-Length either: a client that sees one can
+    // wait for a body that isn't coming, or take the next response
+    // for it.
+    pub fn has_body(&self) -> bool {
+        !(self.status < 200 || self.status == 204 || self.status == 304)
+    }
+
+    // Adds a Set-Cookie header for cookie. Unlike set_header() this
+    // never replaces anything, since each cookie needs a header of
+    // its own.
+    pub fn set_cookie(&mut self, cookie: &Cookie) {
+        self.headers
+            .push((String::from("Set-Cookie"), cookie.to_string()));
+    }
+
+    // Writes the status line, headers and body. Content-Length is
+    // worked out here from the body so it can never disagree with
+    // what we actually send.
+    pub fn write_to<W: Write>(&self, stream: &mut W) -> io::Result<()> {
+        self.write_buffered(stream, WRITE_BUFFER)
+    }
+
+    // write_to() through a buffer of buffer_size bytes.
+    fn write_buffered<W: Write>(&self, stream: &mut W, buffer_size: usize) -> io::Result<()> {
+        let mut out = BufWriter::with_capacity(buffer_size, stream);
+        let written = self
+            .write_head(&mut out)
+            .and_then(|_| self.write_body(&mut out))
+            .and_then(|_| out.flush());
+        abandon_on_error(out, written)
+    }
+
+    // write_to() for a connection, which can send a file body without
+    // copying it through our memory if it knows how (see
+    // Connection::send_file()). buffer_size is how much is gathered
+    // up before each write, see Config::write_buffer_size.
+    pub fn send<C: Connection>(&self, stream: &mut C, buffer_size: usize) -> io::Result<()> {
+        match &self.body {
+            Body::Handle { file, offset, len } if self.has_body() && !self.head_only => {
+                let mut out = BufWriter::with_capacity(buffer_size, &mut *stream);
+                let written = self.write_head(&mut out).and_then(|_| out.flush());
+                abandon_on_error(out, written)?;
+                stream.send_file(file, *offset, *len)
+            }
+            _ => self.write_buffered(stream, buffer_size),
+        }
+    }
+
+    fn write_body<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        if !self.has_body() || self.head_only {
+            return Ok(());
+        }
+        match &self.body {
+            Body::Handle { file, offset, len } => copy_file(file, *offset, *len, out),
+            body => out.write_all(bytes(body)),
+        }
+    }
+
+    // This runs for every response, so the head is written straight
+    // into a buffer instead of being built up from format!() strings
+    // first. The BufWriter also means a small response goes out in a
+    // single write() rather than one per line (and one per packet).
+    fn write_head<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let length = match &self.body {
+            Body::Handle { len, .. } => *len,
+            body => bytes(body).len() as u64,
+        };
+        write!(out, "HTTP/1.1 {} {}\r\n", self.status, reason(self.status))?;
+        for (name, value) in &self.headers {
+            write!(out, "{}: {}\r\n", name, value)?;
+        }
+        if self.has_body() {
+            write!(out, "Content-Length: {}\r\n", length)?;
+        }
+        out.write_all(b"\r\n")
+    }
+}
+
+// A BufWriter that's dropped writes out whatever it's still holding.
+// After a write has failed, usually because the client stopped
+// reading and Config::write_timeout ran out, that would be one more
+// write stuck for as long again, so what's left is thrown away
+// instead. The connection is closed after a failed write anyway.
+fn abandon_on_error<W: Write>(out: BufWriter<W>, written: io::Result<()>) -> io::Result<()> {
+    if written.is_err() {
+        let _ = out.into_parts();
+    }
+    written
+}
+
+// The bytes of a body that's already in memory.
+fn bytes(body: &Body) -> &[u8] {
+    match body {
+        Body::Empty | Body::File(_) | Body::Handle { .. } => &[],
+        Body::Text(text) => text.as_bytes(),
+        Body::Bytes(bytes) => bytes,
+    }
+}
+
+// Copies len bytes of file, from offset, through a buffer. If the
+// file has shrunk since we said how long it was there's no way to
+// make up the difference, so that's an error and the connection
+// gets closed.
+pub(crate) fn copy_file<W: Write + ?Sized>(
+    file: &File,
+    offset: u64,
+    len: u64,
+    out: &mut W,
+) -> io::Result<()> {
+    let mut file = file;
+    file.seek(SeekFrom::Start(offset))?;
+    let copied = io::copy(&mut file.take(len), out)?;
+    if copied < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+// The reason phrase that goes after the status code. Clients
+// ignore it, it's just there for people reading the response.
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        412 => "Precondition Failed",
+        413 => "Content Too Large",
+        414 => "URI Too Long",
+        416 => "Range Not Satisfiable",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        505 => "HTTP Version Not Supported",
+        _ => "",
+    }
+}
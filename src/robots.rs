@@ -0,0 +1,99 @@
+// A robots.txt and sitemap.xml made up from the config, for sites
+// that only need the simple kind and would rather not keep the files
+// by hand:
+//
+//   robots_disallow = /admin/, /drafts/
+//   sitemap = /, /about.html, /blog/
+//
+// gives a /robots.txt of
+//
+//   User-agent: *
+//   Disallow: /admin/
+//   Disallow: /drafts/
+//   Sitemap: https://example.com/sitemap.xml
+//
+// and a /sitemap.xml listing the three pages. Either is only made up
+// when there's no file for it under the static root, so a site can
+// always write its own.
+use crate::config::Config;
+use crate::html::html_escape;
+use crate::request::Request;
+use crate::response::Response;
+
+// The generated file for request, if it's for one the config has
+// something to put in.
+pub fn generate(config: &Config, request: &Request) -> Option<Response> {
+    match request.path.as_str() {
+        "/robots.txt" => robots_txt(config, request),
+        "/sitemap.xml" => sitemap_xml(config, request),
+        _ => None,
+    }
+}
+
+fn robots_txt(config: &Config, request: &Request) -> Option<Response> {
+    if config.robots_allow.is_empty()
+        && config.robots_disallow.is_empty()
+        && config.sitemap.is_empty()
+    {
+        return None;
+    }
+
+    let mut text = String::from("User-agent: *\n");
+    for path in &config.robots_allow {
+        text.push_str(&format!("Allow: {}\n", path));
+    }
+    for path in &config.robots_disallow {
+        text.push_str(&format!("Disallow: {}\n", path));
+    }
+    // A group has to have at least one rule, and an empty Disallow is
+    // the one that means everything's allowed.
+    if config.robots_allow.is_empty() && config.robots_disallow.is_empty() {
+        text.push_str("Disallow:\n");
+    }
+    if !config.sitemap.is_empty() {
+        text.push_str(&format!(
+            "Sitemap: {}\n",
+            absolute(config, request, "/sitemap.xml")
+        ));
+    }
+    Some(Response::text(200, &text))
+}
+
+fn sitemap_xml(config: &Config, request: &Request) -> Option<Response> {
+    if config.sitemap.is_empty() {
+        return None;
+    }
+
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for url in &config.sitemap {
+        xml.push_str(&format!(
+            "  <url><loc>{}</loc></url>\n",
+            html_escape(&absolute(config, request, url))
+        ));
+    }
+    xml.push_str("</urlset>\n");
+
+    let mut response = Response::text(200, &xml);
+    response.set_header("Content-Type", "application/xml; charset=utf-8");
+    Some(response)
+}
+
+// Sitemaps (and the Sitemap line in robots.txt) have to give whole
+// URLs, so a path is put on the end of the site's address: the
+// canonical one if there is one, otherwise the one this request was
+// made to. Anything that's a whole URL already is left alone.
+fn absolute(config: &Config, request: &Request, url: &str) -> String {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        return url.to_string();
+    }
+    let client = request.client();
+    let scheme = config.canonical_scheme.as_deref().unwrap_or(&client.scheme);
+    match config.canonical_host.as_deref().or(client.host.as_deref()) {
+        Some(host) => format!("{}://{}{}", scheme, host, url),
+        // An HTTP/1.0 client might not have said which host it
+        // wanted, and there's no better guess than the path.
+        None => url.to_string(),
+    }
+}
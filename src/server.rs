@@ -0,0 +1,2562 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::auth::{self, Authenticator};
+use crate::builder::ServerBuilder;
+use crate::capture;
+use crate::conditional::{self, Outcome, Resource};
+use crate::config::{self, Config, ConfigError};
+use crate::connection::{Closer, Connection, MemoryConnection, Peer};
+use crate::csrf;
+use crate::error::HttpError;
+use crate::events::{Event, Hook};
+use crate::files;
+use crate::forwarded;
+use crate::glob;
+use crate::httpdate;
+use crate::log;
+use crate::ranges;
+use crate::request::{self, BodyError, BodyReader, HeadError, ParseError, Request};
+use crate::response::{self, Body, Response};
+use crate::robots;
+use crate::session::Sessions;
+use crate::signals;
+use crate::thread_pool::ThreadPool;
+use crate::url;
+use crate::writes;
+
+// A handler is any function that takes a request and returns a
+// response. They have to be Send + Sync because later on more
+// than one thread may be calling the same handler at once.
+pub type Handler = Box<dyn Fn(&Request) -> Response + Send + Sync>;
+
+// A handler that can fail with an HttpError. See route_fallible().
+pub type FallibleHandler = Box<dyn Fn(&Request) -> Result<Response, HttpError> + Send + Sync>;
+
+// A handler that reads the request body itself, a piece at a time,
+// instead of being given all of it up front. See route_streaming().
+pub type StreamingHandler = Box<dyn Fn(&Request, &mut BodyReader) -> Response + Send + Sync>;
+
+// Runs on every request before anything else looks at it, and can
+// change it or answer it. See rewrite().
+pub type Rewriter = Box<dyn Fn(&mut Request) -> Option<Response> + Send + Sync>;
+
+// Where a fresh config comes from when the server is asked to
+// reload, usually Config::load.
+type Loader = Box<dyn Fn() -> Result<Config, ConfigError> + Send + Sync>;
+
+struct Route {
+    method: String,
+    path: String,
+    handler: RouteHandler,
+}
+
+enum RouteHandler {
+    Buffered(Handler),
+    Fallible(FallibleHandler),
+    Streaming(StreamingHandler),
+}
+
+// The server ties the config together with the handlers that have
+// been registered. Anything a handler doesn't claim falls through
+// to the static files under the root.
+//
+// The config sits behind a lock so it can be swapped out while the
+// server runs (see reload()). Each request takes its own Arc of it
+// up front and uses that throughout, so it never sees half of one
+// config and half of another.
+pub struct Server {
+    config: RwLock<Arc<Config>>,
+    loader: Option<Loader>,
+    routes: Vec<Route>,
+    rewriters: Vec<Rewriter>,
+    hooks: Vec<Hook>,
+    authenticators: Vec<(String, Box<dyn Authenticator>)>,
+    sessions: Arc<Sessions>,
+    // How many connections are being answered right now.
+    active: AtomicUsize,
+    // Whether keep-alive is off because of the number of them, see
+    // keep_alive_high_water.
+    shedding: AtomicBool,
+    // How many connections each client address has open, for
+    // max_connections_per_ip. Addresses with none aren't in it.
+    per_ip: Mutex<HashMap<IpAddr, usize>>,
+    // Whether a shutdown has started, see drain().
+    stopping: AtomicBool,
+    // Every connection being answered, by an ID of its own, so a
+    // shutdown can close them.
+    open: Mutex<HashMap<usize, OpenConnection>>,
+    next_id: AtomicUsize,
+}
+
+struct OpenConnection {
+    // None once it's been closed, or if it can't be.
+    closer: Option<Closer>,
+    // Whether it's waiting for the client to start another request,
+    // rather than in the middle of one.
+    idle: bool,
+}
+
+impl Server {
+    pub fn new(config: Config) -> Server {
+        log::set_level(config.log_level);
+        Server {
+            config: RwLock::new(Arc::new(config)),
+            loader: None,
+            routes: Vec::new(),
+            rewriters: Vec::new(),
+            hooks: Vec::new(),
+            authenticators: Vec::new(),
+            sessions: Arc::new(Sessions::new()),
+            active: AtomicUsize::new(0),
+            shedding: AtomicBool::new(false),
+            per_ip: Mutex::new(HashMap::new()),
+            stopping: AtomicBool::new(false),
+            open: Mutex::new(HashMap::new()),
+            next_id: AtomicUsize::new(0),
+        }
+    }
+
+    // For setting a server up in code, one option and handler at a
+    // time. See builder.rs.
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::new()
+    }
+
+    // How many connections are being answered right now. Connections
+    // waiting for a worker don't count until one picks them up.
+    pub fn active_connections(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    // Whether keep-alive should be turned down under the current load.
+    // Past Config::keep_alive_high_water every response closes its
+    // connection, and that carries on until the count falls back to
+    // keep_alive_low_water. Having two marks keeps the server from
+    // flapping between the two when the load sits around one of them.
+    fn shedding_keep_alive(&self, config: &Config) -> bool {
+        let high = match config.keep_alive_high_water {
+            Some(high) => high,
+            None => return false,
+        };
+        let low = config.keep_alive_low_water.unwrap_or(high).min(high);
+        let active = self.active_connections();
+        if active > high && !self.shedding.swap(true, Ordering::Relaxed) {
+            warn!(
+                "{} connections open, closing them after each response until there are {} or fewer",
+                active, low
+            );
+        } else if active <= low && self.shedding.swap(false, Ordering::Relaxed) {
+            info!("{} connections open, keep-alive is back on", active);
+        }
+        self.shedding.load(Ordering::Relaxed)
+    }
+
+    // Marks connection id as waiting for its next request, or not.
+    fn set_idle(&self, id: usize, idle: bool) {
+        if let Some(open) = self.lock_open().get_mut(&id) {
+            open.idle = idle;
+        }
+    }
+
+    // Closes the open connections that are idle, or all of them, and
+    // says how many that was. Each is only closed once.
+    fn close_open(&self, idle_only: bool) -> usize {
+        let mut closed = 0;
+        for open in self.lock_open().values_mut() {
+            if idle_only && !open.idle {
+                continue;
+            }
+            if let Some(closer) = open.closer.take() {
+                closer();
+                closed += 1;
+            }
+        }
+        closed
+    }
+
+    fn lock_open(&self) -> MutexGuard<'_, HashMap<usize, OpenConnection>> {
+        self.open.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // The config as it is right now. A reload doesn't change the one
+    // returned here, it just means the next call gets the new one.
+    pub fn config(&self) -> Arc<Config> {
+        let config = self.config.read().unwrap_or_else(|e| e.into_inner());
+        Arc::clone(&config)
+    }
+
+    // Makes a SIGHUP reload the config from loader once the server
+    // is running. Without this SIGHUP ends the process as usual.
+    pub fn reload_on_hangup<F>(&mut self, loader: F)
+    where
+        F: Fn() -> Result<Config, ConfigError> + Send + Sync + 'static,
+    {
+        self.loader = Some(Box::new(loader));
+    }
+
+    // Swaps in a new config. Requests already being answered finish
+    // with the old one; every request after this gets the new one.
+    //
+    // The listener and the thread pool were set up from the old
+    // config and stay as they are, so changes to addr, unix_socket
+    // and threads are logged and put back, to be picked up on the next restart.
+    pub fn reload(&self, mut config: Config) {
+        let mut current = self.config.write().unwrap_or_else(|e| e.into_inner());
+        if config.addr != current.addr {
+            warn!(
+                "reload: addr changed from {} to {}, restart to apply",
+                current.addr, config.addr
+            );
+            config.addr = current.addr.clone();
+        }
+        if config.unix_socket != current.unix_socket {
+            warn!("reload: unix_socket changed, restart to apply");
+            config.unix_socket = current.unix_socket.clone();
+        }
+        if config.threads != current.threads {
+            warn!(
+                "reload: threads changed from {} to {}, restart to apply",
+                current.threads, config.threads
+            );
+            config.threads = current.threads;
+        }
+        log::set_level(config.log_level);
+        if config.response_delay != current.response_delay {
+            warn_about_delay(&config);
+        }
+        if config.echo_path != current.echo_path {
+            warn_about_echo(&config);
+        }
+        if config.routes_path != current.routes_path {
+            warn_about_routes(&config);
+        }
+        if config.capture_dir != current.capture_dir {
+            warn_about_capture(&config);
+        }
+        *current = Arc::new(config);
+    }
+
+    // Registers a handler for requests with exactly this method
+    // and path. Handlers are checked in the order they were added,
+    // before any static file.
+    pub fn route<F>(&mut self, method: &str, path: &str, handler: F)
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.routes.push(Route {
+            method: method.to_string(),
+            path: path.to_string(),
+            handler: RouteHandler::Buffered(Box::new(handler)),
+        });
+    }
+
+    // Like route(), for a handler that returns a Result so it can use
+    // ? to bail out. Ok is sent as it is. Err(HttpError) becomes an
+    // error response with its status and message, or the error page
+    // for that status if there is one. An error of any other type
+    // that ? converted is a 500, and what it said goes in the log.
+    pub fn route_fallible<F>(&mut self, method: &str, path: &str, handler: F)
+    where
+        F: Fn(&Request) -> Result<Response, HttpError> + Send + Sync + 'static,
+    {
+        self.routes.push(Route {
+            method: method.to_string(),
+            path: path.to_string(),
+            handler: RouteHandler::Fallible(Box::new(handler)),
+        });
+    }
+
+    // Like route(), but the body isn't read before the handler is
+    // called. request.body is left empty and the handler reads the
+    // body from the BodyReader as it arrives instead, so an upload
+    // can go straight to a file (or another server) without ever
+    // being in memory all at once.
+    //
+    // Whatever the handler doesn't read is still on the connection
+    // ahead of the next request, so if it stops early the connection
+    // is closed after the response.
+    pub fn route_streaming<F>(&mut self, method: &str, path: &str, handler: F)
+    where
+        F: Fn(&Request, &mut BodyReader) -> Response + Send + Sync + 'static,
+    {
+        self.routes.push(Route {
+            method: method.to_string(),
+            path: path.to_string(),
+            handler: RouteHandler::Streaming(Box::new(handler)),
+        });
+    }
+
+    // Registers a rewriter, which gets every request as soon as it's
+    // been parsed and can change it before the rest of the server
+    // sees it: strip the prefix the site is mounted at behind a
+    // proxy, lowercase the path, send old URLs to new ones. Returning
+    // a response answers the request with that instead, and no
+    // rewriter after it runs.
+    //
+    // Rewriters run in the order they were added, after the client's
+    // address is worked out from trusted_proxies and before anything
+    // else: method_override, canonical_host redirects, maintenance,
+    // logins, routing and the static files all see the rewritten
+    // request. The path is as it came, still percent-encoded, and the
+    // dot segments files are looked up without are only dropped
+    // later. The access log shows the request as rewritten.
+    pub fn rewrite<F>(&mut self, rewriter: F)
+    where
+        F: Fn(&mut Request) -> Option<Response> + Send + Sync + 'static,
+    {
+        self.rewriters.push(Box::new(rewriter));
+    }
+
+    // Puts request through the rewriters, and returns the response
+    // one of them answered it with, if any did. A rewriter that
+    // panics is treated like a handler that did.
+    fn run_rewriters(&self, config: &Config, request: &mut Request) -> Option<Response> {
+        for rewriter in &self.rewriters {
+            match panic::catch_unwind(AssertUnwindSafe(|| rewriter(request))) {
+                Ok(None) => {}
+                Ok(Some(response)) => return Some(finish(config, request, response)),
+                Err(_) => {
+                    error!("rewriter panicked on {} {}", request.method, request.path);
+                    return Some(error(config, 500));
+                }
+            }
+        }
+        None
+    }
+
+    // Registers an authenticator for Config::protected to name, for
+    // checking logins some other way than basic_auth's fixed lists.
+    // One registered here wins over a basic_auth list with the same
+    // name. Names are matched the way option names are, ignoring case
+    // and with - and _ the same.
+    pub fn authenticator<A>(&mut self, name: &str, authenticator: A)
+    where
+        A: Authenticator + 'static,
+    {
+        let name = config::normalize(name);
+        self.authenticators
+            .retain(|(existing, _)| *existing != name);
+        self.authenticators.push((name, Box::new(authenticator)));
+    }
+
+    // Registers a hook that's called at each point in the life of
+    // every connection (see Event). Hooks run in the order they were
+    // added, on the worker handling the connection.
+    pub fn on_event<F>(&mut self, hook: F)
+    where
+        F: Fn(&Event) + Send + Sync + 'static,
+    {
+        self.hooks.push(Box::new(hook));
+    }
+
+    // A panicking hook is logged and skipped, like a panicking
+    // handler, rather than unwinding out of the connection it was
+    // told about. The other hooks still hear about the event.
+    fn emit(&self, event: &Event) {
+        for hook in &self.hooks {
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| hook(event))) {
+                error!("event hook panicked: {}", panic_message(&*payload));
+            }
+        }
+    }
+
+    // Binds to the configured address (and Unix socket, if there is
+    // one) and serves connections until the process is stopped. Use
+    // bind() and then run() to find out the address first.
+    pub fn run(self) -> io::Result<()> {
+        self.bind()?.run()
+    }
+
+    // Binds the addr and unix_socket the config asks for, without
+    // answering anything yet. With port 0 the OS picks a free port,
+    // and Bound::local_addr() says which, so a test can start a
+    // server and know where to find it:
+    //
+    //   let bound = Server::builder().addr("127.0.0.1:0").build()?.bind()?;
+    //   let addr = bound.local_addr().unwrap();
+    //   thread::spawn(move || bound.run());
+    pub fn bind(self) -> io::Result<Bound> {
+        // TcpListener::bind() is basically a new() function, but
+        // its called bind because in networking you "bind" to a
+        // specific port. bind() returns a Result<T, E>.
+        //
+        // There might be an issue binding to the specified port.
+        // It requires administrative privelages to listen to a
+        // port from 1024 or below.
+        //
+        // An empty addr means no TCP at all, for a server that only
+        // listens on a Unix socket.
+        let config = self.config();
+        warn_about_delay(&config);
+        warn_about_echo(&config);
+        warn_about_routes(&config);
+        warn_about_capture(&config);
+        let listener = if config.addr.is_empty() {
+            None
+        } else {
+            let listener = TcpListener::bind(&config.addr)?;
+            info!("listening on {}", listener.local_addr()?);
+            Some(listener)
+        };
+        let unix = match &config.unix_socket {
+            Some(path) => {
+                let unix = unix_socket::bind(path)?;
+                info!("listening on {}", path.display());
+                Some(unix)
+            }
+            None => None,
+        };
+        if listener.is_none() && unix.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no addr or unix_socket to listen on",
+            ));
+        }
+
+        Ok(Bound {
+            server: self,
+            listener,
+            unix,
+        })
+    }
+
+    // Every method the server can answer somewhere: the ones it
+    // handles itself plus whatever the registered handlers take.
+    // This is the one list of what's implemented: anything not on it
+    // gets a 501, wherever it's sent.
+    pub fn methods(&self) -> Vec<String> {
+        self.methods_for(&self.config())
+    }
+
+    fn methods_for(&self, config: &Config) -> Vec<String> {
+        self.methods_matching(config, None)
+    }
+
+    // The methods that can be used on path, for the Allow header of a
+    // 405 or an OPTIONS request for it.
+    fn methods_at(&self, config: &Config, path: &str) -> Vec<String> {
+        self.methods_matching(config, Some(path))
+    }
+
+    // The methods for path, or for anywhere with None.
+    fn methods_matching(&self, config: &Config, path: Option<&str>) -> Vec<String> {
+        let applies = |at: &str| path.is_none_or(|path| path == at);
+        let mut methods = vec![
+            String::from("GET"),
+            String::from("HEAD"),
+            String::from("OPTIONS"),
+        ];
+        if config.allow_trace {
+            methods.push(String::from("TRACE"));
+        }
+        if config.allow_writes {
+            methods.push(String::from("PUT"));
+            methods.push(String::from("DELETE"));
+        }
+        if config.echo_path.as_deref().is_some_and(applies) {
+            methods.push(String::from("POST"));
+        }
+        for route in self.routes.iter().filter(|route| applies(&route.path)) {
+            if !methods.contains(&route.method) {
+                methods.push(route.method.clone());
+            }
+        }
+        methods
+    }
+
+    // Answers raw, the bytes of one or more requests as a client
+    // would send them, without a socket, and returns everything the
+    // server wrote back. It goes through handle_connnection() like a
+    // real connection does, so the parsing, keep-alive, config
+    // headers, access log and hooks are all the same, which makes it
+    // the way to test handlers:
+    //
+    //   let output = server.dispatch(b"GET /hello HTTP/1.1\r\nHost: x\r\n\r\n");
+    //   assert!(output.starts_with(b"HTTP/1.1 200 OK\r\n"));
+    //
+    // The requests come from 127.0.0.1, and the connection closes
+    // once they've all been answered.
+    pub fn dispatch(&self, raw: &[u8]) -> Vec<u8> {
+        let mut connection = MemoryConnection::new(raw);
+        let peer = Peer::Tcp(SocketAddr::from(([127, 0, 0, 1], 0)));
+        handle_connnection(&mut connection, peer, self);
+        connection.output
+    }
+
+    // Works out the response for a request: a registered handler
+    // if one matches, otherwise a file from the static root. A
+    // streaming handler reads request.body.
+    pub fn respond(&self, request: &Request) -> Response {
+        let config = self.config();
+        let mut request = request.clone();
+        if let Some(response) = self.run_rewriters(&config, &mut request) {
+            return response;
+        }
+        let (mut stream, mut buffer) = (io::empty(), request.body.clone());
+        let framing = request::Framing::Length(buffer.len());
+        let mut body = BodyReader::new(&mut stream, &mut buffer, framing, &config);
+        self.respond_with(&config, &request, &mut body)
+    }
+
+    // Checks request with the authenticator Config::protected names
+    // for its path, if it names one, and returns the identity that
+    // gives. Err is the response to send instead: a 401 to say the
+    // client has to log in, or a 500 if there's no authenticator by
+    // that name.
+    fn authenticate(&self, config: &Config, request: &Request) -> Result<Option<String>, Response> {
+        // The path is also checked the way files are looked up, with
+        // escapes decoded and empty and . segments dropped, or
+        // /%61dmin/ and //admin/ would miss a pattern for /admin/*
+        // and still get the files in it.
+        let normalized = normalize_path(&request.path);
+        let name = config.protected.iter().find_map(|(name, patterns)| {
+            patterns
+                .iter()
+                .any(|pattern| {
+                    glob::matches(pattern, &request.path)
+                        || normalized
+                            .as_deref()
+                            .is_some_and(|path| glob::matches(pattern, path))
+                })
+                .then_some(name)
+        });
+        let name = match name {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+
+        let registered = self
+            .authenticators
+            .iter()
+            .find(|(existing, _)| existing == name)
+            .map(|(_, authenticator)| authenticator.as_ref());
+        let basic = config
+            .basic_auth
+            .iter()
+            .find(|auth| auth.realm == *name)
+            .map(|auth| auth as &dyn Authenticator);
+        let authenticator = match registered.or(basic) {
+            Some(authenticator) => authenticator,
+            None => {
+                error!(
+                    "{} is protected by {:?}, but there's no authenticator called that",
+                    request.path, name
+                );
+                return Err(error(config, 500));
+            }
+        };
+
+        match authenticator.authenticate(request) {
+            auth::Outcome::Authorized(identity) => Ok(identity),
+            auth::Outcome::Unauthorized => {
+                let mut response = error(config, 401);
+                response.set_header("WWW-Authenticate", &authenticator.challenge());
+                Err(response)
+            }
+        }
+    }
+
+    // Whether request is going to a streaming handler, which means
+    // its body has to be left for the handler to read.
+    fn streams(&self, config: &Config, request: &Request) -> bool {
+        let method = method_override(config, request).unwrap_or_else(|| request.method.clone());
+        let health = config.health_path.as_deref() == Some(request.path.as_str());
+        if (config.maintenance && !health) || request.path == "*" || method == "TRACE" {
+            return false;
+        }
+        self.find_route(&method, &request.path)
+            .is_some_and(|route| matches!(route.handler, RouteHandler::Streaming(_)))
+    }
+
+    // The route for method at path. HEAD is answered by a HEAD route
+    // if there is one, and otherwise the same as a GET would be, with
+    // the body left off when the response is sent.
+    fn find_route(&self, method: &str, path: &str) -> Option<&Route> {
+        let find = |method: &str| {
+            self.routes
+                .iter()
+                .find(|route| route.method == method && route.path == path)
+        };
+        match find(method) {
+            None if method == "HEAD" => find("GET"),
+            route => route,
+        }
+    }
+
+    // The whole of a request is answered from the one config, so a
+    // reload halfway through can't leave it seeing some of each.
+    fn respond_with(&self, config: &Config, request: &Request, body: &mut BodyReader) -> Response {
+        let overridden;
+        let request = match method_override(config, request) {
+            Some(method) => {
+                overridden = Request {
+                    method,
+                    ..request.clone()
+                };
+                &overridden
+            }
+            None => request,
+        };
+
+        let health = config.health_path.as_deref() == Some(request.path.as_str());
+        if !health {
+            if let Some(response) = canonical_redirect(config, request) {
+                return response;
+            }
+        }
+        if config.maintenance && !health {
+            return maintenance(config);
+        }
+
+        let identified;
+        let request = match self.authenticate(config, request) {
+            Ok(None) => request,
+            Ok(identity) => {
+                identified = Request {
+                    identity,
+                    ..request.clone()
+                };
+                &identified
+            }
+            Err(response) => return response,
+        };
+        let echo = config.echo_path.as_deref() == Some(request.path.as_str());
+        let table = config.routes_path.as_deref() == Some(request.path.as_str());
+
+        // A target of * doesn't name a resource, it means the
+        // server as a whole. The only thing that makes sense to ask
+        // about it is OPTIONS, so handle that before routing ever
+        // sees a path that isn't a path.
+        if request.path == "*" {
+            if request.method != "OPTIONS" {
+                return error(config, 400);
+            }
+            let mut response = Response::new(200);
+            response.set_header("Allow", &self.methods_for(config).join(", "));
+            return response;
+        }
+
+        if request.method == "TRACE" {
+            return self.trace(config, request);
+        }
+
+        // A method nothing here answers, on any path, isn't one we
+        // implement at all, which is a 501. One that's answered
+        // somewhere, just not at this path, is a 405 further down.
+        let methods = self.methods_for(config);
+        if !methods.contains(&request.method) {
+            let mut response = error(config, 501);
+            response.set_header("Allow", &methods.join(", "));
+            return response;
+        }
+
+        let route = self.find_route(&request.method, &request.path);
+        let get = request.method == "GET" || request.method == "HEAD";
+
+        let response = match route {
+            Some(route) => self.call(config, route, request, body),
+            None if health && get => Response::text(200, "ok\n"),
+            None if echo && request.method == "POST" => self.echo(request),
+            None if table && get => self.route_table(config),
+            None if request.method == "PUT" || request.method == "DELETE" => {
+                return self.write(config, request)
+            }
+            None if request.method == "OPTIONS" => {
+                let mut response = Response::new(200);
+                response.set_header("Allow", &self.methods_at(config, &request.path).join(", "));
+                return response;
+            }
+            None if !get => {
+                let mut response = error(config, 405);
+                response.set_header("Allow", &self.methods_at(config, &request.path).join(", "));
+                return response;
+            }
+            None => Response::file(&request.path),
+        };
+
+        let response = finish(config, request, response);
+        // A robots.txt or sitemap.xml that's really there is served
+        // like any other file. Only when there isn't one is one made
+        // up from the config.
+        if route.is_none() && response.status == 404 {
+            if let Some(generated) = robots::generate(config, request) {
+                return generated;
+            }
+        }
+        response
+    }
+
+    // PUT and DELETE, which change the static root and so share the
+    // allow_writes switch.
+    fn write(&self, config: &Config, request: &Request) -> Response {
+        if !config.allow_writes {
+            let mut response = error(config, 405);
+            response.set_header("Allow", &self.methods_at(config, &request.path).join(", "));
+            return response;
+        }
+        let result = if request.method == "PUT" {
+            writes::put(config, request)
+        } else {
+            writes::delete(config, request)
+        };
+        result.unwrap_or_else(|status| error(config, status))
+    }
+
+    // Sends the request back as the body so the client can see what
+    // actually reached us.
+    fn trace(&self, config: &Config, request: &Request) -> Response {
+        if !config.allow_trace {
+            let mut response = error(config, 405);
+            response.set_header("Allow", &self.methods_at(config, &request.path).join(", "));
+            return response;
+        }
+
+        let mut response = Response::text(200, &echo_head(request));
+        response.set_header("Content-Type", "message/http");
+        response
+    }
+
+    // Runs route's handler, in the request's session if there are
+    // sessions, once it's passed the CSRF check if there's one.
+    fn call(
+        &self,
+        config: &Config,
+        route: &Route,
+        request: &Request,
+        body: &mut BodyReader,
+    ) -> Response {
+        let in_session;
+        let request = if config.sessions || config.csrf_protection {
+            match self.sessions.open(config, request) {
+                Ok(session) => {
+                    in_session = Request {
+                        session: Some(session),
+                        ..request.clone()
+                    };
+                    &in_session
+                }
+                Err(e) => {
+                    error!("couldn't start a session: {}", e);
+                    request
+                }
+            }
+        } else {
+            request
+        };
+        if let Some(message) = not_utf8(config, request) {
+            return error_with(config, 400, &message);
+        }
+        if config.csrf_protection && csrf::needs_check(&request.method) && !csrf::verify(request) {
+            debug!(
+                "refusing {} {}: no CSRF token or the wrong one",
+                request.method, request.path
+            );
+            return error(config, 403);
+        }
+
+        let mut response = match &route.handler {
+            RouteHandler::Buffered(handler) => handler(request),
+            RouteHandler::Fallible(handler) => match handler(request) {
+                Ok(response) => response,
+                Err(e) => {
+                    if let Some(cause) = e.cause() {
+                        error!(
+                            "handler failed on {} {}: {}",
+                            request.method, request.path, cause
+                        );
+                    }
+                    error_with(config, e.status, &e.message)
+                }
+            },
+            RouteHandler::Streaming(handler) => handler(request, body),
+        };
+        if let Some(session) = request.session() {
+            self.sessions.close(config, request, session, &mut response);
+        }
+        response
+    }
+
+    // The echo endpoint at Config::echo_path: the request as we parsed
+    // it, head and body, so a client can see what we made of it. The
+    // body comes back byte for byte, already put together if it was
+    // chunked, followed by any trailers.
+    fn echo(&self, request: &Request) -> Response {
+        let mut echo = echo_head(request).into_bytes();
+        echo.extend_from_slice(b"\r\n");
+        echo.extend_from_slice(&request.body);
+        if !request.trailers.is_empty() {
+            echo.extend_from_slice(b"\r\n");
+            for (name, value) in &request.trailers {
+                echo.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+            }
+        }
+
+        let mut response = Response::new(200);
+        response.set_header("Content-Type", "text/plain; charset=utf-8");
+        response.body = Body::Bytes(echo);
+        response
+    }
+
+    // The endpoint at Config::routes_path: one line for each route,
+    // its method, path and what answers it, in the order they're
+    // checked, so an earlier line wins over a later one for the same
+    // request. * stands for any path.
+    fn route_table(&self, config: &Config) -> Response {
+        let mut table: Vec<(&str, &str, String)> = Vec::new();
+        if let Some(host) = &config.canonical_host {
+            let scheme = match &config.canonical_scheme {
+                _ if config.https_redirect => "https",
+                Some(scheme) => scheme,
+                None => "the same scheme",
+            };
+            table.push(("*", "*", format!("redirect to {} on {}", host, scheme)));
+        } else if config.https_redirect {
+            table.push(("*", "*", String::from("redirect plain http to https")));
+        }
+        if config.maintenance {
+            table.push(("*", "*", String::from("maintenance page")));
+        }
+        for route in &self.routes {
+            let kind = match route.handler {
+                RouteHandler::Streaming(_) => "streaming handler",
+                _ => "handler",
+            };
+            table.push((&route.method, &route.path, String::from(kind)));
+        }
+        if let Some(path) = &config.health_path {
+            table.push(("GET", path, String::from("health check")));
+        }
+        if let Some(path) = &config.echo_path {
+            table.push(("POST", path, String::from("echo")));
+        }
+        if let Some(path) = &config.routes_path {
+            table.push(("GET", path, String::from("route table")));
+        }
+        if config.allow_trace {
+            table.push(("TRACE", "*", String::from("trace")));
+        }
+        if config.allow_writes {
+            table.push(("PUT", "*", String::from("static file upload")));
+            table.push(("DELETE", "*", String::from("static file delete")));
+        }
+        table.push(("OPTIONS", "*", String::from("allowed methods")));
+        table.push(("GET", "*", String::from("static files")));
+        if !config.robots_allow.is_empty()
+            || !config.robots_disallow.is_empty()
+            || !config.sitemap.is_empty()
+        {
+            table.push((
+                "GET",
+                "/robots.txt",
+                String::from("generated, without a file"),
+            ));
+        }
+        if !config.sitemap.is_empty() {
+            table.push((
+                "GET",
+                "/sitemap.xml",
+                String::from("generated, without a file"),
+            ));
+        }
+
+        let width = table
+            .iter()
+            .map(|(method, _, _)| method.len())
+            .max()
+            .unwrap_or(0);
+        let path_width = table
+            .iter()
+            .map(|(_, path, _)| path.len())
+            .max()
+            .unwrap_or(0);
+        let mut text = String::new();
+        for (method, path, kind) in &table {
+            text.push_str(&format!(
+                "{:width$} {:path_width$} {}\n",
+                method,
+                path,
+                kind,
+                width = width,
+                path_width = path_width
+            ));
+        }
+        Response::text(200, &text)
+    }
+
+    // The response sent when something goes wrong.
+    pub fn error(&self, status: u16) -> Response {
+        error(&self.config(), status)
+    }
+
+    // An error response with message as its body, unless there's an
+    // error page configured for the status, which is sent instead.
+    pub fn error_with(&self, status: u16, message: &str) -> Response {
+        error_with(&self.config(), status, message)
+    }
+}
+
+// A server with its sockets bound, from Server::bind(), ready to
+// run().
+pub struct Bound {
+    server: Server,
+    listener: Option<TcpListener>,
+    unix: Option<unix_socket::Listener>,
+}
+
+impl Bound {
+    // The address the server's listening on over TCP, with the port
+    // the OS picked if addr asked for port 0. None when it's only on
+    // a Unix socket.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener
+            .as_ref()
+            .and_then(|listener| listener.local_addr().ok())
+    }
+
+    // Answers connections until the process ends.
+    pub fn run(self) -> io::Result<()> {
+        // Connections are handed off to the pool so a slow one
+        // doesn't hold up everyone behind it. The pool threads all
+        // need the server, hence the Arc.
+        let config = self.server.config();
+        // Where the shutdown thread connects to wake the accept loops.
+        let wake = Wake {
+            tcp: self.local_addr(),
+            unix: self.unix.as_ref().and(config.unix_socket.clone()),
+        };
+        let pool = Arc::new(ThreadPool::new(
+            config.threads,
+            config.max_queued_connections,
+        ));
+        let server = Arc::new(self.server);
+
+        signals::watch_termination();
+        let shutdown = {
+            let (pool, server) = (Arc::clone(&pool), Arc::clone(&server));
+            thread::Builder::new()
+                .name(String::from("shutdown"))
+                .spawn(move || watch_for_termination(&server, &pool, &wake))?
+        };
+
+        if server.loader.is_some() {
+            signals::watch_hangup();
+            let server = Arc::clone(&server);
+            thread::Builder::new()
+                .name(String::from("reload"))
+                .spawn(move || watch_for_reload(&server))?;
+        }
+
+        let unix_thread = match self.unix {
+            Some(unix) => {
+                let (pool, server) = (Arc::clone(&pool), Arc::clone(&server));
+                Some(
+                    thread::Builder::new()
+                        .name(String::from("unix-accept"))
+                        .spawn(move || unix_socket::accept(unix, &pool, &server))?,
+                )
+            }
+            None => None,
+        };
+
+        // The accept loops only end once a shutdown has started.
+        // Letting go of the listeners then means new connections are
+        // refused outright while the rest drain, and the shutdown
+        // thread ends the process when that's done.
+        if let Some(listener) = self.listener {
+            accept(listener.incoming(), &pool, &server);
+        }
+        if let Some(thread) = unix_thread {
+            let _ = thread.join();
+        }
+        let _ = shutdown.join();
+
+        Ok(())
+    }
+}
+
+// The request line and headers of request, the way TRACE and the echo
+// endpoint send them back.
+fn echo_head(request: &Request) -> String {
+    let mut echo = format!(
+        "{} {} {}\r\n",
+        request.method,
+        request.target(),
+        request.version
+    );
+    for (name, value) in &request.headers {
+        // Credentials are the whole reason TRACE is dangerous,
+        // so they never go back out.
+        let sensitive = ["Authorization", "Proxy-Authorization", "Cookie"]
+            .iter()
+            .any(|header| name.eq_ignore_ascii_case(header));
+        if !sensitive {
+            echo.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    echo
+}
+
+// The method a POST asked to be treated as, if overrides are on.
+// Only methods a POST could reasonably stand in for are allowed,
+// so an override can't turn a POST into a TRACE or a CONNECT.
+fn method_override(config: &Config, request: &Request) -> Option<String> {
+    if !config.method_override || request.method != "POST" {
+        return None;
+    }
+
+    let method = request
+        .header("X-HTTP-Method-Override")?
+        .to_ascii_uppercase();
+    match method.as_str() {
+        "PUT" | "PATCH" | "DELETE" => Some(method),
+        _ => None,
+    }
+}
+
+// path with its escapes decoded and without empty or . segments,
+// which is how files::resolve() reads it. None if it doesn't decode.
+fn normalize_path(path: &str) -> Option<String> {
+    let decoded = url::percent_decode(path)?;
+    let mut normalized = String::with_capacity(decoded.len());
+    for segment in decoded.split('/') {
+        if !segment.is_empty() && segment != "." {
+            normalized.push('/');
+            normalized.push_str(segment);
+        }
+    }
+    if normalized.is_empty() || decoded.ends_with('/') {
+        normalized.push('/');
+    }
+    Some(normalized)
+}
+
+// The redirect to Config::canonical_host, or to https for
+// Config::https_redirect, for a request that came in on some other
+// name or scheme for the site, or None if it's already there.
+fn canonical_redirect(config: &Config, request: &Request) -> Option<Response> {
+    if config.canonical_host.is_none() && !config.https_redirect {
+        return None;
+    }
+    if request.path == "*" {
+        return None;
+    }
+    let client = request.client();
+    let scheme = if config.https_redirect {
+        "https"
+    } else {
+        config.canonical_scheme.as_deref().unwrap_or(&client.scheme)
+    };
+    // Without canonical_host it stays on the host that was asked for,
+    // and with no Host at all (HTTP/1.0) there's nowhere to send it.
+    let host = config
+        .canonical_host
+        .as_deref()
+        .or(client.host.as_deref())?;
+    let same_host = client
+        .host
+        .as_deref()
+        .is_some_and(|asked| asked.eq_ignore_ascii_case(host));
+    if same_host && scheme == client.scheme {
+        return None;
+    }
+
+    // A 301 can turn a POST into a GET on the way, which 308 can't, so
+    // anything but a GET or HEAD keeps its method and body.
+    let status = if request.method == "GET" || request.method == "HEAD" {
+        301
+    } else {
+        308
+    };
+    Some(Response::redirect(
+        status,
+        &format!("{}://{}{}", scheme, host, request.target()),
+    ))
+}
+
+// The page every request gets in maintenance mode.
+fn maintenance(config: &Config) -> Response {
+    let mut response = match fs::read_to_string(&config.maintenance_page) {
+        Ok(page) => Response::html(503, &page),
+        Err(_) => error(config, 503),
+    };
+    response.set_header("Retry-After", &config.retry_after.as_secs().to_string());
+    response
+}
+
+// Fills in a file body. Whether the file came from a plain
+// request or a handler picking it, it goes through
+// files::serve() and so gets the same path checks and headers.
+fn finish(config: &Config, request: &Request, mut response: Response) -> Response {
+    let target = match &response.body {
+        Body::File(target) => target.clone(),
+        _ => return response,
+    };
+
+    match files::serve(config, request, &target) {
+        Ok(file) => {
+            // Serving can end in something other than the file
+            // itself, like a redirect to add a trailing /.
+            if file.status != 200 {
+                response.status = file.status;
+            }
+            // Headers the handler set win over the defaults.
+            for (name, value) in file.headers {
+                if response.header(&name).is_none() {
+                    response.headers.push((name, value));
+                }
+            }
+            response.body = file.body;
+            check_preconditions(config, request, response)
+        }
+        Err(status) => error(config, status),
+    }
+}
+
+// Applies If-Match, If-None-Match and friends to a file response,
+// going off the ETag and Last-Modified it's about to be sent with.
+fn check_preconditions(config: &Config, request: &Request, mut response: Response) -> Response {
+    if response.status != 200 {
+        return response;
+    }
+
+    let resource = Resource {
+        exists: true,
+        etag: response.header("ETag"),
+        last_modified: response.header("Last-Modified").and_then(httpdate::parse),
+    };
+
+    match conditional::evaluate(request, &resource) {
+        Outcome::Proceed => ranges::apply(request, response),
+        // The client's copy is fine. The validators and caching
+        // headers still go out so it can update what it has
+        // stored, only the body is left off.
+        Outcome::NotModified => {
+            response.status = 304;
+            response.body = Body::Empty;
+            response
+                .headers
+                .retain(|(name, _)| !name.eq_ignore_ascii_case("Content-Type"));
+            response
+        }
+        Outcome::Failed => error(config, 412),
+    }
+}
+
+fn error(config: &Config, status: u16) -> Response {
+    let status = hide_forbidden(config, status);
+    error_with(
+        config,
+        status,
+        &format!("{} {}", status, response::reason(status)),
+    )
+}
+
+fn error_with(config: &Config, status: u16, message: &str) -> Response {
+    let hidden = hide_forbidden(config, status);
+    if hidden != status {
+        return error(config, hidden);
+    }
+    let Some(page) = config.error_pages.get(&status) else {
+        return Response::text(status, message);
+    };
+    // A page whose file can't be read falls back to the message, but
+    // still goes out with the status and type it was given.
+    let contents = page.file.as_ref().and_then(|file| fs::read(file).ok());
+    let mut response = match (&page.file, contents) {
+        (Some(file), Some(contents)) => {
+            let mut response = Response::new(status);
+            // Error pages used to always be HTML, so one with an
+            // extension we don't know still is.
+            let content_type = match files::content_type(file) {
+                files::UNKNOWN_TYPE => "text/html; charset=utf-8",
+                content_type => content_type,
+            };
+            response.set_header("Content-Type", content_type);
+            response.body = Body::Bytes(contents);
+            response
+        }
+        _ => Response::text(status, message),
+    };
+    if let Some(content_type) = &page.content_type {
+        response.set_header("Content-Type", content_type);
+    }
+    if let Some(status) = page.status {
+        response.status = status;
+    }
+    response
+}
+
+// response_delay makes every client wait on purpose, which nobody
+// wants to find out about from their users, so it's hard to miss in
+// the log.
+fn warn_about_delay(config: &Config) {
+    if let Some(delay) = config.response_delay {
+        warn!(
+            "response_delay is on: responses will be held back {:?} before being sent. This is for testing only",
+            delay
+        );
+    }
+}
+
+// Nor is an endpoint sending requests back to whoever made them.
+fn warn_about_echo(config: &Config) {
+    if let Some(path) = &config.echo_path {
+        warn!(
+            "echo_path is on: POST {} sends every request back as it came. This is for debugging only",
+            path
+        );
+    }
+}
+
+// Or one showing anyone who asks how the server is laid out.
+fn warn_about_routes(config: &Config) {
+    if let Some(path) = &config.routes_path {
+        warn!(
+            "routes_path is on: GET {} lists every route to anyone who asks. This is for debugging only",
+            path
+        );
+    }
+}
+
+// Capturing saves everything clients send, secrets and all.
+fn warn_about_capture(config: &Config) {
+    if let Some(dir) = &config.capture_dir {
+        warn!(
+            "capture_dir is on: every request is being saved to {}, passwords and cookies included. This is for debugging only",
+            dir.display()
+        );
+    }
+}
+
+// Whether response_delay applies to request.
+fn delayed(config: &Config, request: &Request) -> Option<Duration> {
+    let delay = config.response_delay?;
+    let applies = config.response_delay_paths.is_empty()
+        || config
+            .response_delay_paths
+            .iter()
+            .any(|pattern| glob::matches(pattern, &request.path));
+    if applies {
+        Some(delay)
+    } else {
+        None
+    }
+}
+
+// What's wrong with request's Host headers, if anything. HTTP/1.1
+// needs exactly one, and one or none for other versions (RFC 9112
+// section 3.2). With two, a proxy and the server could each go by a
+// different one and disagree about which site a request is for.
+fn bad_host(request: &Request) -> Option<String> {
+    let hosts = request
+        .headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Host"))
+        .count();
+    if hosts > 1 {
+        Some(String::from("400 Bad Request: more than one Host header\n"))
+    } else if hosts == 0 && request.version == "HTTP/1.1" {
+        Some(String::from(
+            "400 Bad Request: HTTP/1.1 requests need a Host header\n",
+        ))
+    } else {
+        None
+    }
+}
+
+// Why request's body should be refused under Config::utf8_body_paths,
+// or None if it's fine. A streamed body hasn't been read yet, so
+// there's nothing to check.
+fn not_utf8(config: &Config, request: &Request) -> Option<String> {
+    if request.body.is_empty()
+        || !config
+            .utf8_body_paths
+            .iter()
+            .any(|pattern| glob::matches(pattern, &request.path))
+    {
+        return None;
+    }
+    let content_type = request.header("Content-Type")?;
+    let mut params = content_type.split(';');
+    let mime = params.next().unwrap_or("").trim().to_ascii_lowercase();
+    let text = mime.starts_with("text/")
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+        || matches!(
+            mime.as_str(),
+            "application/json" | "application/xml" | "application/x-www-form-urlencoded"
+        );
+    let charset = params
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_ascii_lowercase());
+    if !text || charset.is_some_and(|charset| charset != "utf-8" && charset != "utf8") {
+        return None;
+    }
+    match std::str::from_utf8(&request.body) {
+        Ok(_) => None,
+        Err(e) => Some(format!(
+            "400 Bad Request: the body isn't valid UTF-8 (from byte {})\n",
+            e.valid_up_to()
+        )),
+    }
+}
+
+// With Config::hide_forbidden a 403 goes out as a 404, so a refused
+// path looks exactly like a missing one.
+fn hide_forbidden(config: &Config, status: u16) -> u16 {
+    if config.hide_forbidden && status == 403 {
+        404
+    } else {
+        status
+    }
+}
+
+// Hands each connection from connections to the pool.
+fn accept<C, I>(connections: I, pool: &ThreadPool, server: &Arc<Server>)
+where
+    C: Connection + Send + 'static,
+    I: Iterator<Item = io::Result<C>>,
+{
+    // listener.incoming() gives us an iterator of a sequence of
+    // streams. A stream is an open connection between the client
+    // and the server. A connection is the name for the whole
+    // process of the client opening a connection with the server,
+    // the server generates a response, and the server closes
+    // the connection.
+    //
+    // listener.incoming() actually iterates over connection attempts,
+    // meaning it's possible that they'll fail. They might fail
+    // for many reasons, many of them OS specific. For example,
+    // OS's only have so many connections the can have open at once.
+    // If too many connections are trying to be made then some
+    // will be dropped until others are closed.
+    //
+    // None of that is a reason to stop serving everyone else, so
+    // a failed accept is logged and the loop carries on. Out of
+    // file descriptors, the next accept would fail straight away
+    // too, so it waits a moment first for some to be closed.
+    for stream in connections {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) if out_of_descriptors(&e) => {
+                warn!("couldn't accept a connection: {}", e);
+                thread::sleep(ACCEPT_BACKOFF);
+                continue;
+            }
+            Err(e) => {
+                debug!("couldn't accept a connection: {}", e);
+                continue;
+            }
+        };
+
+        // Once a shutdown has started nothing new is answered. This
+        // is usually wake_listeners()'s own connection, there to get
+        // us out of waiting for one.
+        if server.stopping.load(Ordering::SeqCst) {
+            break;
+        }
+
+        // Without knowing who's on the other end there's nobody to
+        // answer, and it usually means they've already gone.
+        let peer = match stream.peer() {
+            Ok(peer) => peer,
+            Err(_) => continue,
+        };
+
+        let slot = match IpSlot::take(server, peer) {
+            Some(slot) => slot,
+            None => {
+                debug!("{}: too many connections from this address", peer);
+                turn_away(stream, &server.config());
+                continue;
+            }
+        };
+
+        // Backpressure: with every worker busy and the queue full,
+        // another connection would only wait even longer, so it's
+        // told to come back later instead.
+        let worker = Arc::clone(server);
+        let queued = pool.try_execute((stream, slot), move |(stream, slot)| {
+            handle_connnection(stream, peer, &worker);
+            drop(slot);
+        });
+        if let Err((stream, slot)) = queued {
+            drop(slot);
+            debug!("{}: every worker is busy and the queue is full", peer);
+            turn_away(stream, &server.config());
+        }
+    }
+}
+
+// How long accept() waits after running out of file descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+// Whether e is EMFILE or ENFILE, the process or the whole system
+// having as many files open as it's allowed. They're 24 and 23 on
+// Linux and macOS alike.
+fn out_of_descriptors(e: &io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(23) | Some(24))
+}
+
+// One of a client address's max_connections_per_ip, held for as long
+// as the connection is open, or waiting to be. It's given back when
+// it's dropped, so a worker that panics still gives it back.
+struct IpSlot {
+    server: Arc<Server>,
+    ip: Option<IpAddr>,
+}
+
+impl IpSlot {
+    // A slot for a new connection from peer, or None if its address
+    // already has as many as it's allowed.
+    fn take(server: &Arc<Server>, peer: Peer) -> Option<IpSlot> {
+        let config = server.config();
+        let ip = match (peer, config.max_connections_per_ip) {
+            (Peer::Tcp(addr), Some(_)) if !config.trusted_proxies.contains(&addr.ip()) => {
+                Some(addr.ip())
+            }
+            _ => None,
+        };
+        if let (Some(ip), Some(limit)) = (ip, config.max_connections_per_ip) {
+            let mut per_ip = server.per_ip.lock().unwrap_or_else(|e| e.into_inner());
+            let count = per_ip.entry(ip).or_insert(0);
+            if *count >= limit {
+                return None;
+            }
+            *count += 1;
+        }
+        Some(IpSlot {
+            server: Arc::clone(server),
+            ip,
+        })
+    }
+}
+
+impl Drop for IpSlot {
+    fn drop(&mut self) {
+        let ip = match self.ip {
+            Some(ip) => ip,
+            None => return,
+        };
+        let mut per_ip = self.server.per_ip.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = per_ip.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                per_ip.remove(&ip);
+            }
+        }
+    }
+}
+
+// Answers a connection we don't have room for with a 503. This runs
+// on the thread accepting connections, so the socket is made
+// non-blocking: a short response fits in the send buffer, and if it
+// somehow doesn't, the client misses out rather than everyone else.
+fn turn_away<C: Connection>(mut stream: C, config: &Config) {
+    let mut response = error(config, 503);
+    response.set_header("Retry-After", &config.retry_after.as_secs().to_string());
+    response.set_header("Connection", "close");
+    let _ = stream.set_nonblocking(true);
+    let _ = response.write_to(&mut stream);
+}
+
+// The addresses the server is listening on, for wake_listeners().
+struct Wake {
+    tcp: Option<SocketAddr>,
+    unix: Option<PathBuf>,
+}
+
+// Shuts the server down gracefully when SIGTERM or Ctrl-C comes in,
+// then ends the process.
+fn watch_for_termination(server: &Server, pool: &ThreadPool, wake: &Wake) {
+    while !signals::take_termination() {
+        thread::sleep(Duration::from_millis(200));
+    }
+    server.stopping.store(true, Ordering::SeqCst);
+    wake_listeners(wake);
+    drain(server, pool);
+    // A Unix socket is a file, and would otherwise be left behind.
+    if let Some(path) = &server.config().unix_socket {
+        let _ = fs::remove_file(path);
+    }
+    process::exit(0);
+}
+
+// The accept loops sit blocked in accept() until a connection comes
+// in, so they'd only notice a shutdown had started with the next
+// client. Connecting to each listener ourselves gives them that
+// connection straight away, without them having to poll.
+fn wake_listeners(wake: &Wake) {
+    if let Some(mut addr) = wake.tcp {
+        // A listener on every interface is reached over loopback.
+        if addr.ip().is_unspecified() {
+            addr.set_ip(match addr {
+                SocketAddr::V4(_) => IpAddr::from([127, 0, 0, 1]),
+                SocketAddr::V6(_) => IpAddr::from(Ipv6Addr::LOCALHOST),
+            });
+        }
+        if let Err(e) = TcpStream::connect_timeout(&addr, Duration::from_secs(1)) {
+            warn!(
+                "shutdown: couldn't wake the listener on {}, it'll stop at its next connection: {}",
+                addr, e
+            );
+        }
+    }
+    if let Some(path) = &wake.unix {
+        unix_socket::wake(path);
+    }
+}
+
+// Waits up to drain_timeout for the connections being answered to
+// finish, once Server::stopping is set so no new ones are taken.
+// Every response from now on closes its connection, and a connection
+// waiting for its next request is closed straight away rather than
+// left until the client sends one. Whatever's still going when the
+// time runs out is closed anyway.
+fn drain(server: &Server, pool: &ThreadPool) {
+    let config = server.config();
+    info!(
+        "shutting down, waiting up to {:?} for {} connections to finish",
+        config.drain_timeout,
+        server.active_connections()
+    );
+
+    let deadline = Instant::now() + config.drain_timeout;
+    loop {
+        // Checked over and over, since busy connections become idle
+        // ones as they finish, and queued ones only show up once a
+        // worker gets to them.
+        let idle = server.close_open(true);
+        if idle > 0 {
+            debug!("shutdown: closed {} idle connections", idle);
+        }
+        if server.active_connections() == 0 && pool.queued() == 0 {
+            info!("shutdown: every connection has finished");
+            return;
+        }
+        if Instant::now() >= deadline {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    let forced = server.close_open(false);
+    warn!(
+        "shutdown: drain_timeout ran out, closed {} connections that were still busy",
+        forced
+    );
+}
+
+// Reloads the config whenever a SIGHUP comes in. Signals only set a
+// flag (see signals.rs), so this checks it a few times a second.
+fn watch_for_reload(server: &Server) {
+    let loader = match &server.loader {
+        Some(loader) => loader,
+        None => return,
+    };
+    loop {
+        thread::sleep(Duration::from_millis(200));
+        if !signals::take_hangup() {
+            continue;
+        }
+        // A config that doesn't load leaves the old one running,
+        // which is better than a server with no config at all.
+        match loader() {
+            Ok(config) => {
+                server.reload(config);
+                info!("reload: config reloaded");
+            }
+            Err(e) => error!("reload: keeping the old config: {}", e),
+        }
+    }
+}
+
+// Answers every request on a connection until it closes. Any
+// Connection works: a TcpStream, a UnixStream, or a MemoryConnection
+// to drive the server without a socket. peer is passed in rather
+// than asked for, since a stream that isn't a socket has no address
+// of its own.
+//
+// The stream needs to be mutable because it keeps internal state
+// of what data has been accessed and that needs to be able
+// to change.
+pub fn handle_connnection<C: Connection>(stream: C, peer: Peer, server: &Server) {
+    let opened = Instant::now();
+    let open = OpenGuard::new(server, stream.closer());
+    let id = open.id;
+
+    server.emit(&Event::ConnectionAccepted { peer });
+    debug!("{}: connection opened", peer);
+
+    // Keep-alive: rather than one request per connection, keep
+    // answering requests on the same socket until the client asks us
+    // to stop, goes quiet for longer than keep_alive_timeout, uses up
+    // max_keep_alive_requests or gets older than max_connection_age.
+    // Skipping the TCP handshake is most of the cost of a small
+    // request.
+    //
+    // The read timeout is what turns a silent client into an error
+    // from read() instead of a worker stuck waiting on them forever.
+    // Nagle's algorithm would hold back a small response waiting for
+    // more to send with it, and with keep-alive there isn't any, so
+    // it's switched off.
+    let config = server.config();
+    let capture = config
+        .capture_dir
+        .as_ref()
+        .map(|_| config.capture_max_bytes);
+    let mut stream = capture::Recorder::new(stream, capture);
+    let _ = stream.set_read_timeout(Some(config.keep_alive_timeout));
+    let _ = stream.set_write_timeout(Some(config.write_timeout));
+    let _ = stream.set_nodelay(true);
+
+    // Anything read past the end of one request is the start of the
+    // next, so the buffer lives as long as the connection.
+    //
+    // Pipelined requests are answered one at a time, in the order
+    // they came in: each response is written in full before the next
+    // request is even parsed, so responses can't get mixed up.
+    // Anything already in the buffer when we start on a request was
+    // sent before the client had the last response, so that's how we
+    // count them.
+    let mut buffer = Vec::new();
+    let mut pipelined = 0;
+    for served in 1.. {
+        pipelined = if buffer.is_empty() { 0 } else { pipelined + 1 };
+        let last =
+            served >= config.max_keep_alive_requests || pipelined >= config.max_pipelined_requests;
+        // Nothing in the buffer means we're waiting on the client,
+        // with no request started.
+        server.set_idle(id, buffer.is_empty());
+        stream.start(&buffer);
+        let open = serve_request(&mut stream, &mut buffer, peer, server, id, opened, last);
+        if let Some(dir) = &config.capture_dir {
+            save_capture(&mut stream, buffer.len(), dir, peer);
+        }
+        if !open {
+            break;
+        }
+    }
+
+    drop(open);
+    debug!("{}: connection closed after {:?}", peer, opened.elapsed());
+    server.emit(&Event::ConnectionClosed {
+        peer,
+        duration: opened.elapsed(),
+    });
+}
+
+// A connection counted in Server::active and listed in Server::open
+// for as long as this is around. Dropping it takes the connection off
+// both, so one that ends in a panic is still taken off, and keep-alive
+// shedding and drain() don't go on counting it.
+struct OpenGuard<'a> {
+    server: &'a Server,
+    id: usize,
+}
+
+impl<'a> OpenGuard<'a> {
+    fn new(server: &'a Server, closer: Option<Closer>) -> OpenGuard<'a> {
+        server.active.fetch_add(1, Ordering::Relaxed);
+        let id = server.next_id.fetch_add(1, Ordering::Relaxed);
+        server
+            .lock_open()
+            .insert(id, OpenConnection { closer, idle: true });
+        OpenGuard { server, id }
+    }
+}
+
+impl Drop for OpenGuard<'_> {
+    fn drop(&mut self) {
+        self.server.lock_open().remove(&self.id);
+        self.server.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Saves the request the recorder just saw to dir, for capture_dir.
+// left is how much of what it read belongs to the next request.
+fn save_capture<C: Connection>(
+    recorder: &mut capture::Recorder<C>,
+    left: usize,
+    dir: &Path,
+    peer: Peer,
+) {
+    let (bytes, cut) = recorder.finish(left);
+    // An idle connection closing never sent a request at all.
+    if bytes.is_empty() {
+        return;
+    }
+    match capture::save(dir, &bytes) {
+        Ok(path) if cut => debug!(
+            "{}: captured the first {} bytes of the request to {}",
+            peer,
+            bytes.len(),
+            path.display()
+        ),
+        Ok(path) => debug!("{}: captured the request to {}", peer, path.display()),
+        Err(e) => warn!(
+            "{}: couldn't capture the request to {}: {}",
+            peer,
+            dir.display(),
+            e
+        ),
+    }
+}
+
+// Reads one request off the stream and writes its response.
+// Returns whether the connection should stay open for another.
+// opened is when the connection was, and last says this is the
+// final request we'll take on it.
+fn serve_request<C: Connection>(
+    stream: &mut C,
+    buffer: &mut Vec<u8>,
+    peer: Peer,
+    server: &Server,
+    id: usize,
+    opened: Instant,
+    last: bool,
+) -> bool {
+    let config = server.config();
+    let started = Instant::now();
+
+    let head = request::read_head(stream, buffer, &config);
+    server.set_idle(id, false);
+    let head = match head {
+        Ok(head) => Ok(head),
+        // The limits are easy to hit by accident (a huge query
+        // string, a pile of cookies), so say which one it was.
+        Err(HeadError::RequestLineTooLong) => Err(error_with(
+            &config,
+            414,
+            &format!(
+                "414 URI Too Long\n\nThe request line is longer than the {} bytes this server accepts.\n",
+                config.max_request_line
+            ),
+        )),
+        Err(HeadError::HeaderTooLarge) => Err(error_with(
+            &config,
+            431,
+            &format!(
+                "431 Request Header Fields Too Large\n\nThe request headers are larger than the {} bytes this server accepts.\n",
+                config.max_header_size
+            ),
+        )),
+        // There's no telling a client speaking TLS anything in plain
+        // HTTP, so it's just hung up on. Scanners do this all the time,
+        // so it's only worth a debug line.
+        Err(HeadError::TlsHandshake) => {
+            debug!(
+                "{}: sent a TLS handshake, but this port speaks plain HTTP, closing the connection",
+                peer
+            );
+            return false;
+        }
+        // This is also where idle keep-alive connections end up,
+        // when the client closes or the read timeout runs out.
+        Err(HeadError::Io(e)) => {
+            debug!("{}: stopped reading: {}", peer, e);
+            return false;
+        }
+    };
+
+    // in_sync says whether we know where this request ends, so
+    // whatever's left in the buffer is the start of the next one.
+    let (request, mut response, in_sync) = match head {
+        Ok(head) => match Request::parse(&buffer[..head]) {
+            Ok(mut request) => {
+                request.deadline = Instant::now() + config.max_request_duration;
+                request.client = forwarded::client(&config, peer, &request);
+                debug!(
+                    "{}: read a {} byte head:\n{}",
+                    peer,
+                    head,
+                    String::from_utf8_lossy(&buffer[..head]).trim_end()
+                );
+                buffer.drain(..head);
+                // Nothing else is read of a request this is wrong
+                // with, so the connection is closed after it.
+                if let Some(message) = bad_host(&request) {
+                    debug!("{}: {}", peer, message.trim_end());
+                    let response = error_with(&config, 400, &message);
+                    (Some(request), response, false)
+                } else {
+                    // A request a rewriter answers still has its body
+                    // read, so the next one starts in the right place.
+                    let rewritten = server.run_rewriters(&config, &mut request);
+                    // Only a body the method can have is left to a
+                    // streaming handler. read_body() deals with the rest.
+                    let read = if rewritten.is_none()
+                        && server.streams(&config, &request)
+                        && request::takes_body(&config, &request)
+                    {
+                        request::framing(&request).map(Some)
+                    } else {
+                        request::read_body(stream, buffer, &mut request, &config).map(|_| None)
+                    };
+                    match read {
+                        // The body is left where it is for the handler to
+                        // read from the stream.
+                        Ok(Some(framing)) => {
+                            debug!("{}: leaving the body to the handler", peer);
+                            server.emit(&Event::RequestParsed {
+                                peer,
+                                request: &request,
+                            });
+                            let mut body = BodyReader::new(stream, buffer, framing, &config);
+                            let response = respond_catching_panics(server, &config, &request, &mut body);
+                            let finished = body.finished();
+                            (Some(request), response, finished)
+                        }
+                        Ok(None) => {
+                            debug!("{}: read a {} byte body", peer, request.body.len());
+                            server.emit(&Event::RequestParsed {
+                                peer,
+                                request: &request,
+                            });
+                            let (mut empty, mut nothing) = (io::empty(), Vec::new());
+                            let mut body = BodyReader::new(
+                                &mut empty,
+                                &mut nothing,
+                                request::Framing::Length(0),
+                                &config,
+                            );
+                            let response = match rewritten {
+                                Some(response) => response,
+                                None => respond_catching_panics(server, &config, &request, &mut body),
+                            };
+                            (Some(request), response, true)
+                        }
+                        Err(BodyError::TooLarge) => (
+                            Some(request),
+                            error_with(
+                                &config,
+                                413,
+                                &format!(
+                                    "413 Content Too Large\n\nThe request body is larger than the {} bytes this server accepts.\n",
+                                    config.max_body_size
+                                ),
+                            ),
+                            false,
+                        ),
+                        Err(BodyError::Invalid) => (Some(request), error(&config, 400), false),
+                        Err(BodyError::Unsupported) => (Some(request), error(&config, 501), false),
+                        Err(BodyError::Unexpected) => {
+                            let message = format!(
+                                "400 Bad Request: {} requests can't have a body\n",
+                                request.method
+                            );
+                            (Some(request), error_with(&config, 400, &message), false)
+                        }
+                        // A client that stops partway through the body is
+                        // told so once the read timeout runs out, rather
+                        // than just being hung up on. One that's already
+                        // gone can't be told anything.
+                        Err(BodyError::Io(e))
+                            if matches!(
+                                e.kind(),
+                                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                            ) =>
+                        {
+                            debug!("{}: gave up waiting for the body: {}", peer, e);
+                            (Some(request), error(&config, 408), false)
+                        }
+                        Err(BodyError::Io(e)) => {
+                            debug!("{}: stopped reading the body: {}", peer, e);
+                            return false;
+                        }
+                    }
+                }
+            }
+            Err(ParseError::Malformed) => (None, error(&config, 400), false),
+            Err(ParseError::Version) => (None, error(&config, 505), false),
+        },
+        Err(response) => (None, response, false),
+    };
+
+    for (name, value) in &config.headers {
+        if response.header(name).is_none() {
+            response.headers.push((name.clone(), value.clone()));
+        }
+    }
+    // These go after Config::headers so those can override them.
+    let security_headers: &[(&str, &str)] = if config.security_headers {
+        &config::SECURITY_HEADERS
+    } else {
+        &[]
+    };
+    let policy = config
+        .content_security_policy
+        .as_deref()
+        .map(|policy| ("Content-Security-Policy", policy));
+    for (name, value) in security_headers.iter().copied().chain(policy) {
+        if response.header(name).is_none() {
+            response.set_header(name, value);
+        }
+    }
+
+    // Once a request has gone wrong we can't tell where the next
+    // one would start, so errors like that always close the
+    // connection.
+    //
+    // The connection's age is checked now rather than before the
+    // request, since waiting for it can be what took it past
+    // max_connection_age.
+    let shedding = server.shedding_keep_alive(&config);
+    let aged = config
+        .max_connection_age
+        .is_some_and(|age| opened.elapsed() >= age);
+    let stopping = server.stopping.load(Ordering::SeqCst);
+    let keep_alive = in_sync
+        && !last
+        && !aged
+        && !shedding
+        && !stopping
+        && request.as_ref().is_some_and(wants_keep_alive);
+    if !keep_alive {
+        response.set_header("Connection", "close");
+    } else if request
+        .as_ref()
+        .is_some_and(|request| request.version == "HTTP/1.0")
+    {
+        // A 1.0 client takes the connection to be closing unless the
+        // response says otherwise.
+        response.set_header("Connection", "keep-alive");
+    }
+    debug!(
+        "{}: {}",
+        peer,
+        if keep_alive {
+            "keeping the connection open"
+        } else if !in_sync {
+            "closing, can't tell where the next request starts"
+        } else if last {
+            "closing, that's as many requests as it gets"
+        } else if aged {
+            "closing, the connection is older than max_connection_age"
+        } else if shedding {
+            "closing, there are too many connections to keep this one open"
+        } else if stopping {
+            "closing, the server is shutting down"
+        } else {
+            "closing, the client asked to"
+        }
+    );
+
+    if let Some(delay) = request
+        .as_ref()
+        .and_then(|request| delayed(&config, request))
+    {
+        debug!("{}: holding the response back {:?}", peer, delay);
+        thread::sleep(delay);
+    }
+
+    // Past the deadline the response is abandoned. We can't stop a
+    // handler that's still running, but we can stop waiting on it
+    // once it's done, and on a client that's slow to take the
+    // response, by giving the write only the time that's left.
+    if let Some(request) = &request {
+        let left = request.deadline().saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            warn!(
+                "{}: {} {} ran past its deadline, closing the connection",
+                peer, request.method, request.path
+            );
+            return false;
+        }
+        let _ = stream.set_write_timeout(Some(left.min(config.write_timeout)));
+    }
+
+    // HEAD gets everything a GET would have, Content-Length included,
+    // except the body.
+    if request
+        .as_ref()
+        .is_some_and(|request| request.method == "HEAD")
+    {
+        response.head_only = true;
+    }
+
+    // If the client has already hung up there's nobody left to
+    // tell, so a failed write is simply dropped, and since the
+    // response never made it out nobody hears about it being sent.
+    if let Err(e) = response.send(stream, config.write_buffer_size) {
+        match &request {
+            Some(request) if request.expired() => warn!(
+                "{}: {} {} ran past its deadline while writing, closing the connection",
+                peer, request.method, request.path
+            ),
+            // A client that stops reading fills the socket's buffers
+            // until a write blocks, broken or on purpose (a slow read
+            // attack), either way worth knowing about.
+            _ if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+            {
+                warn!(
+                    "{}: stopped reading the response, gave up after write_timeout ({:?}), closing the connection",
+                    peer, config.write_timeout
+                )
+            }
+            _ => debug!("{}: couldn't write the response: {}", peer, e),
+        }
+        return false;
+    }
+    let _ = stream.set_write_timeout(Some(config.write_timeout));
+    let took = started.elapsed();
+    // The access log names the client, which behind a trusted proxy
+    // isn't the peer.
+    info!(
+        "{} \"{}\" {} {:.1}ms",
+        request
+            .as_ref()
+            .map_or(peer.to_string(), |request| request.client().to_string()),
+        request
+            .as_ref()
+            .map_or(String::from("-"), |request| format!(
+                "{} {} {}",
+                request.method,
+                request.target(),
+                request.version
+            )),
+        response.status,
+        took.as_secs_f64() * 1000.0
+    );
+    if let (Some(threshold), Some(request)) = (config.slow_request_threshold, &request) {
+        if took > threshold {
+            warn!(
+                "{}: slow request: {} {} took {:.1}ms, answered {}",
+                peer,
+                request.method,
+                request.path,
+                took.as_secs_f64() * 1000.0,
+                response.status
+            );
+        }
+    }
+    server.emit(&Event::ResponseSent {
+        peer,
+        request: request.as_ref(),
+        status: response.status,
+        duration: took,
+    });
+
+    keep_alive
+}
+
+// Whether the client is happy for the connection to stay open after
+// this request. HTTP/1.1 connections stay open unless the client
+// says Connection: close. HTTP/1.0 ones close unless it says
+// Connection: keep-alive, which 1.0 clients that can keep a
+// connection open send to ask for it. Anything older always closes.
+fn wants_keep_alive(request: &Request) -> bool {
+    if connection_option(request, "close") {
+        return false;
+    }
+    match request.version.as_str() {
+        "HTTP/1.1" => true,
+        "HTTP/1.0" => connection_option(request, "keep-alive"),
+        _ => false,
+    }
+}
+
+// Whether option is one of the comma separated options in the
+// request's Connection header, ignoring case.
+fn connection_option(request: &Request, option: &str) -> bool {
+    request.header("Connection").is_some_and(|value| {
+        value
+            .split(',')
+            .any(|token| token.trim().eq_ignore_ascii_case(option))
+    })
+}
+
+// A panicking handler would otherwise unwind straight out of the
+// worker thread and take it down with it, leaving the pool one
+// thread smaller for good. catch_unwind() stops the unwinding here
+// so the client gets a 500 and the worker moves on to the next job.
+//
+// AssertUnwindSafe is our promise that nothing the handler could
+// leave half-updated is looked at again afterwards. The request is
+// thrown away and handlers only get shared references, apart from a
+// streaming handler's body, and all we ask that afterwards is whether
+// it got to the end. If it didn't, the connection is closed.
+fn respond_catching_panics(
+    server: &Server,
+    config: &Config,
+    request: &Request,
+    body: &mut BodyReader,
+) -> Response {
+    match panic::catch_unwind(AssertUnwindSafe(|| {
+        server.respond_with(config, request, body)
+    })) {
+        Ok(response) => response,
+        Err(payload) => {
+            error!(
+                "handler panicked on {} {}: {}",
+                request.method,
+                request.path,
+                panic_message(&*payload)
+            );
+            error(config, 500)
+        }
+    }
+}
+
+// What a panic said. panic!() payloads are almost always a &str or a
+// String.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(|s| s.as_str()))
+        .unwrap_or("unknown panic")
+}
+
+// When we recieve a request, the first line is the request line.
+// Method Request-URI HTTP-Version CRLF
+// I already know what all of those are except CRLF
+// CRLF stands for carriage return and line feed. It's a vestige
+// from the typewriter days. Basically all it is is \r\n, which
+// just starts a new line when printed.
+
+// When we send back a response, they have this format:
+// HTTP-Version Status-Code Reason-Phrase CRLF
+// headers CRLF
+// message-body
+
+// Listening on a Unix socket, for when the server sits behind a proxy
+// on the same machine. Needs the unix-socket feature.
+#[cfg(all(unix, feature = "unix-socket"))]
+mod unix_socket {
+    use std::fs;
+    use std::io;
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    use super::Server;
+    use crate::thread_pool::ThreadPool;
+
+    pub struct Listener {
+        listener: UnixListener,
+        path: PathBuf,
+    }
+
+    // Unlike a TCP port, a Unix socket is a file, and it stays there
+    // after the server's gone. If one's already at path and nothing
+    // answers on it, it's left over from a server that didn't get to
+    // clean up, and is replaced. One that does answer means another
+    // server is using it.
+    pub fn bind(path: &Path) -> io::Result<Listener> {
+        let listener = match UnixListener::bind(path) {
+            Ok(listener) => listener,
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                let stale = fs::symlink_metadata(path)
+                    .map(|metadata| metadata.file_type().is_socket())
+                    .unwrap_or(false)
+                    && UnixStream::connect(path).is_err();
+                if !stale {
+                    return Err(e);
+                }
+                fs::remove_file(path)?;
+                UnixListener::bind(path)?
+            }
+            Err(e) => return Err(e),
+        };
+
+        Ok(Listener {
+            listener,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn accept(listener: Listener, pool: &ThreadPool, server: &Arc<Server>) {
+        super::accept(listener.listener.incoming(), pool, server);
+    }
+
+    // See wake_listeners().
+    pub fn wake(path: &Path) {
+        let _ = UnixStream::connect(path);
+    }
+
+    impl Drop for Listener {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+// Without the feature there's nothing to bind, so asking for a Unix
+// socket is an error rather than something silently ignored.
+#[cfg(not(all(unix, feature = "unix-socket")))]
+mod unix_socket {
+    use std::io;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use super::Server;
+    use crate::thread_pool::ThreadPool;
+
+    pub enum Listener {}
+
+    pub fn bind(_path: &Path) -> io::Result<Listener> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "unix_socket needs the server built with the unix-socket feature on Unix",
+        ))
+    }
+
+    pub fn accept(listener: Listener, _pool: &ThreadPool, _server: &Arc<Server>) {
+        match listener {}
+    }
+
+    pub fn wake(_path: &Path) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::RawRequest;
+
+    // The head of the response in output, as text, and its body.
+    fn split(output: &[u8]) -> (String, Vec<u8>) {
+        let end = output
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .expect("no end to the response's head");
+        let head = String::from_utf8_lossy(&output[..end]).into_owned();
+        (head, output[end + 4..].to_vec())
+    }
+
+    // The value of the header called name in head.
+    fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+        head.lines().skip(1).find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    }
+
+    fn request(method: &str, target: &str) -> Vec<u8> {
+        RawRequest::new(method, target).header("Host", "x").build()
+    }
+
+    #[test]
+    fn a_panicking_handler_gets_a_500_and_the_server_carries_on() {
+        let server = Server::builder()
+            .route("GET", "/panic", |_| panic!("handler panicked on purpose"))
+            .route("GET", "/fine", |_| Response::text(200, "fine"))
+            .build()
+            .unwrap();
+        let (head, _) = split(&server.dispatch(&request("GET", "/panic")));
+        assert!(head.starts_with("HTTP/1.1 500 "), "{}", head);
+        let (head, body) = split(&server.dispatch(&request("GET", "/fine")));
+        assert!(head.starts_with("HTTP/1.1 200 OK"), "{}", head);
+        assert_eq!(body, b"fine");
+    }
+
+    // The status line of the response to raw.
+    fn status(server: &Server, raw: &[u8]) -> String {
+        let (head, _) = split(&server.dispatch(raw));
+        head.lines().next().unwrap_or("").to_string()
+    }
+
+    #[test]
+    fn http_1_1_needs_exactly_one_host() {
+        let server = Server::builder().build().unwrap();
+        let none = RawRequest::new("GET", "/").build();
+        let one = RawRequest::new("GET", "/").header("Host", "a").build();
+        let two = RawRequest::new("GET", "/")
+            .header("Host", "a")
+            .header("host", "b")
+            .build();
+        assert_eq!(status(&server, &none), "HTTP/1.1 400 Bad Request");
+        assert_eq!(status(&server, &one), "HTTP/1.1 200 OK");
+        assert_eq!(status(&server, &two), "HTTP/1.1 400 Bad Request");
+    }
+
+    #[test]
+    fn http_1_0_can_leave_host_out_but_not_repeat_it() {
+        let server = Server::builder().build().unwrap();
+        let none = RawRequest::new("GET", "/").version("HTTP/1.0").build();
+        let two = RawRequest::new("GET", "/")
+            .version("HTTP/1.0")
+            .header("Host", "a")
+            .header("Host", "a")
+            .build();
+        assert_eq!(status(&server, &none), "HTTP/1.1 200 OK");
+        assert_eq!(status(&server, &two), "HTTP/1.1 400 Bad Request");
+    }
+
+    #[test]
+    fn versions_other_than_1_0_and_1_1_are_turned_down() {
+        let server = Server::builder().build().unwrap();
+        let request = |version| {
+            RawRequest::new("GET", "/")
+                .version(version)
+                .header("Host", "a")
+                .build()
+        };
+        assert_eq!(
+            status(&server, &request("HTTP/2.0")),
+            "HTTP/1.1 505 HTTP Version Not Supported"
+        );
+        assert_eq!(status(&server, &request("FOO")), "HTTP/1.1 400 Bad Request");
+        assert_eq!(
+            status(&server, &request("HTTP/1")),
+            "HTTP/1.1 400 Bad Request"
+        );
+    }
+
+    #[test]
+    fn failed_accepts_dont_stop_the_accept_loop() {
+        let closed = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&closed);
+        let server = Server::builder()
+            .on_event(move |event| {
+                if let Event::ConnectionClosed { .. } = event {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+            .build()
+            .unwrap();
+        let server = Arc::new(server);
+        let pool = ThreadPool::new(1, 4);
+        let connections = vec![
+            Err(io::Error::from(io::ErrorKind::ConnectionAborted)),
+            Err(io::Error::from_raw_os_error(24)),
+            Ok(MemoryConnection::new(&request("GET", "/"))),
+        ];
+        accept(connections.into_iter(), &pool, &server);
+        // Dropping the pool waits for the connection to be answered.
+        drop(pool);
+        assert_eq!(closed.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn a_panicking_hook_leaves_the_connection_counted_out() {
+        let server = Server::builder()
+            .on_event(|event| {
+                if let Event::ConnectionAccepted { .. } = event {
+                    panic!("hook panicked on purpose");
+                }
+            })
+            .build()
+            .unwrap();
+        assert_eq!(status(&server, &request("GET", "/")), "HTTP/1.1 200 OK");
+        assert_eq!(server.active.load(Ordering::SeqCst), 0);
+        assert!(server.lock_open().is_empty());
+    }
+
+    // A server with a route that answers with the length of the body
+    // it was sent.
+    fn counting_server() -> Server {
+        Server::builder()
+            .route("POST", "/count", |request| {
+                Response::text(200, &request.body.len().to_string())
+            })
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn a_body_reaches_the_handler() {
+        let server = counting_server();
+        let raw = RawRequest::new("POST", "/count")
+            .header("Host", "a")
+            .body("hello")
+            .build();
+        let (head, body) = split(&server.dispatch(&raw));
+        assert!(head.starts_with("HTTP/1.1 200 OK"), "{}", head);
+        assert_eq!(body, b"5");
+    }
+
+    #[test]
+    fn a_chunked_body_reaches_the_handler_put_together() {
+        let server = counting_server();
+        let raw = RawRequest::new("POST", "/count")
+            .header("Host", "a")
+            .body("hello, world")
+            .chunked()
+            .build();
+        let (_, body) = split(&server.dispatch(&raw));
+        assert_eq!(body, b"12");
+    }
+
+    #[test]
+    fn a_body_without_a_length_is_taken_for_the_next_request() {
+        let server = counting_server();
+        let raw = RawRequest::new("POST", "/count")
+            .header("Host", "a")
+            .body("hello\r\n\r\n")
+            .without_content_length()
+            .build();
+        let output = server.dispatch(&raw);
+        let (head, rest) = split(&output);
+        assert!(head.starts_with("HTTP/1.1 200 OK"), "{}", head);
+        // The handler saw no body, and "hello" got a 400 of its own.
+        assert!(rest.starts_with(b"0HTTP/1.1 400 Bad Request"));
+    }
+
+    #[test]
+    fn a_body_shorter_than_its_length_gets_no_answer() {
+        let server = counting_server();
+        let raw = RawRequest::new("POST", "/count")
+            .header("Host", "a")
+            .body("hello")
+            .content_length(10)
+            .build();
+        assert!(server.dispatch(&raw).is_empty());
+    }
+
+    #[test]
+    fn a_head_without_its_blank_line_gets_no_answer() {
+        let server = Server::builder().build().unwrap();
+        let raw = RawRequest::new("GET", "/")
+            .header("Host", "a")
+            .without_blank_line()
+            .build();
+        assert!(server.dispatch(&raw).is_empty());
+    }
+
+    #[test]
+    fn bare_newlines_never_end_the_head() {
+        let server = Server::builder().build().unwrap();
+        let raw = RawRequest::new("GET", "/")
+            .header("Host", "a")
+            .line_ending("\n")
+            .build();
+        assert!(server.dispatch(&raw).is_empty());
+    }
+
+    #[test]
+    fn requests_are_routed_by_method_and_path() {
+        let server = counting_server();
+        assert_eq!(
+            status(&server, &request("POST", "/elsewhere")),
+            "HTTP/1.1 405 Method Not Allowed"
+        );
+        assert_eq!(
+            status(&server, &request("GET", "/no-such-file")),
+            "HTTP/1.1 404 Not Found"
+        );
+        assert_eq!(
+            status(&server, &request("BREW", "/count")),
+            "HTTP/1.1 501 Not Implemented"
+        );
+    }
+
+    fn bad_request(target: &str) {
+        let server = Server::builder().build().unwrap();
+        assert_eq!(
+            status(&server, &request("GET", target)),
+            "HTTP/1.1 400 Bad Request",
+            "{:?}",
+            target
+        );
+    }
+
+    #[test]
+    fn an_empty_target_is_a_bad_request() {
+        bad_request("");
+    }
+
+    #[test]
+    fn a_target_not_starting_with_a_slash_is_a_bad_request() {
+        bad_request("index.html");
+    }
+
+    #[test]
+    fn a_target_with_control_characters_is_a_bad_request() {
+        bad_request("/a\u{1}b");
+        bad_request("/a\u{7f}b");
+    }
+
+    // request("GET", "/") after count blank lines.
+    fn after_blank_lines(count: usize) -> Vec<u8> {
+        let mut raw = b"\r\n".repeat(count);
+        raw.extend(request("GET", "/"));
+        raw
+    }
+
+    #[test]
+    fn blank_lines_before_a_request_are_skipped() {
+        let server = Server::builder().build().unwrap();
+        assert_eq!(status(&server, &after_blank_lines(2)), "HTTP/1.1 200 OK");
+    }
+
+    #[test]
+    fn too_many_blank_lines_before_a_request_are_a_bad_request() {
+        let server = Server::builder().build().unwrap();
+        let raw = after_blank_lines(request::MAX_BLANK_LINES + 1);
+        assert_eq!(status(&server, &raw), "HTTP/1.1 400 Bad Request");
+    }
+
+    #[test]
+    fn head_gets_the_head_of_a_get() {
+        let server = Server::builder().build().unwrap();
+        let (get, body) = split(&server.dispatch(&request("GET", "/")));
+        let (head, nothing) = split(&server.dispatch(&request("HEAD", "/")));
+        assert!(head.starts_with("HTTP/1.1 200 OK"), "{}", head);
+        assert_eq!(
+            header(&head, "Content-Length"),
+            Some(body.len().to_string().as_str())
+        );
+        assert_eq!(header(&head, "ETag"), header(&get, "ETag"));
+        assert!(nothing.is_empty());
+    }
+
+    #[test]
+    fn head_is_routed_to_the_get_handler() {
+        let server = Server::builder()
+            .route("GET", "/hello", |_| Response::text(200, "hello"))
+            .build()
+            .unwrap();
+        let (head, body) = split(&server.dispatch(&request("HEAD", "/hello")));
+        assert!(head.starts_with("HTTP/1.1 200 OK"), "{}", head);
+        assert_eq!(header(&head, "Content-Length"), Some("5"));
+        assert!(body.is_empty());
+    }
+
+    #[test]
+    fn head_on_a_bytes_route_gets_the_length_without_the_bytes() {
+        let server = Server::builder()
+            .route("GET", "/image", |_| {
+                Response::bytes(200, "image/png", vec![0x89, b'P', b'N', b'G', 0, 1, 2])
+            })
+            .build()
+            .unwrap();
+        let (_, bytes) = split(&server.dispatch(&request("GET", "/image")));
+        let (head, body) = split(&server.dispatch(&request("HEAD", "/image")));
+        assert!(head.starts_with("HTTP/1.1 200 OK"), "{}", head);
+        assert_eq!(header(&head, "Content-Type"), Some("image/png"));
+        assert_eq!(
+            header(&head, "Content-Length"),
+            Some(bytes.len().to_string().as_str())
+        );
+        assert!(body.is_empty());
+    }
+
+    #[test]
+    fn head_is_an_implemented_method() {
+        let server = Server::builder().build().unwrap();
+        let (head, _) = split(&server.dispatch(&request("OPTIONS", "*")));
+        let allow = header(&head, "Allow").unwrap();
+        assert!(
+            allow.split(", ").any(|method| method == "HEAD"),
+            "{}",
+            allow
+        );
+    }
+}
@@ -0,0 +1,186 @@
+// Sessions: a little state kept on the server for each browser,
+// found again from one request to the next by an ID in a cookie. A
+// handler gets the one its request belongs to from
+// Request::session(), and anything it sets there is there for the
+// next request from the same browser:
+//
+//   if let Some(session) = request.session() {
+//       session.set("user", "alice");
+//   }
+//   ...
+//   let user = request.session().and_then(|session| session.get("user"));
+//
+// Sessions only exist in memory, so they're gone after a restart,
+// and each server behind a load balancer has its own.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::cookie::{Cookie, SameSite};
+use crate::request::Request;
+use crate::response::Response;
+
+// How often the store is swept for sessions that have gone idle for
+// longer than Config::session_timeout. An expired session is never
+// used in between, it just takes up room until then.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+// Every session the server knows about, by ID.
+#[derive(Debug)]
+pub(crate) struct Sessions {
+    sessions: Mutex<HashMap<String, SessionData>>,
+    pruned: Mutex<Instant>,
+}
+
+#[derive(Debug)]
+struct SessionData {
+    values: HashMap<String, String>,
+    used: Instant,
+}
+
+impl Sessions {
+    pub fn new() -> Sessions {
+        Sessions {
+            sessions: Mutex::new(HashMap::new()),
+            pruned: Mutex::new(Instant::now()),
+        }
+    }
+
+    // The session request belongs to, going by its cookie, or a new
+    // one if it isn't in one that's still going. A new session isn't
+    // kept (and the browser isn't sent a cookie for it) unless
+    // something is set in it, so visitors who never log in or add to
+    // a cart don't each leave one behind.
+    pub fn open(self: &Arc<Self>, config: &Config, request: &Request) -> io::Result<Session> {
+        let now = Instant::now();
+        self.prune(config, now);
+
+        if let Some(id) = request.cookies.get(&config.session_cookie) {
+            let mut sessions = self.lock();
+            match sessions.get_mut(id) {
+                Some(data) if now.duration_since(data.used) < config.session_timeout => {
+                    data.used = now;
+                    return Ok(Session {
+                        id: id.clone(),
+                        existed: true,
+                        store: Arc::clone(self),
+                    });
+                }
+                Some(_) => {
+                    sessions.remove(id);
+                }
+                None => {}
+            }
+        }
+
+        Ok(Session {
+            id: random_id()?,
+            existed: false,
+            store: Arc::clone(self),
+        })
+    }
+
+    // Tells the browser about a session the handler started, or that
+    // one it ended is gone.
+    pub fn close(
+        &self,
+        config: &Config,
+        request: &Request,
+        session: &Session,
+        response: &mut Response,
+    ) {
+        let exists = self.lock().contains_key(&session.id);
+        if exists == session.existed {
+            return;
+        }
+        let mut cookie = Cookie::new(&config.session_cookie, &session.id);
+        cookie.path = Some(String::from("/"));
+        cookie.http_only = true;
+        cookie.same_site = Some(SameSite::Lax);
+        cookie.secure = request.client().scheme == "https";
+        if !exists {
+            cookie.max_age = Some(Duration::from_secs(0));
+        }
+        response.set_cookie(&cookie);
+    }
+
+    fn prune(&self, config: &Config, now: Instant) {
+        let mut pruned = self.pruned.lock().unwrap_or_else(|e| e.into_inner());
+        if now.duration_since(*pruned) < PRUNE_INTERVAL {
+            return;
+        }
+        *pruned = now;
+        self.lock()
+            .retain(|_, data| now.duration_since(data.used) < config.session_timeout);
+    }
+
+    // A handler that panics while holding the lock leaves it
+    // poisoned, but every change to the map is a single step, so
+    // what's in it is still fine to use.
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, SessionData>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// The session a request belongs to. See Request::session().
+#[derive(Debug, Clone)]
+pub struct Session {
+    id: String,
+    // Whether the browser already has it, so we know whether to send
+    // it a cookie.
+    existed: bool,
+    store: Arc<Sessions>,
+}
+
+impl Session {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.store
+            .lock()
+            .get(&self.id)
+            .and_then(|data| data.values.get(key).cloned())
+    }
+
+    // Sets key to value, starting the session if it hadn't been.
+    pub fn set(&self, key: &str, value: &str) {
+        self.store
+            .lock()
+            .entry(self.id.clone())
+            .or_insert_with(|| SessionData {
+                values: HashMap::new(),
+                used: Instant::now(),
+            })
+            .values
+            .insert(key.to_string(), value.to_string());
+    }
+
+    pub fn remove(&self, key: &str) -> Option<String> {
+        self.store
+            .lock()
+            .get_mut(&self.id)
+            .and_then(|data| data.values.remove(key))
+    }
+
+    // Forgets the session and everything in it, as when logging out.
+    // The browser is told to drop its cookie, and its next request
+    // starts a new session with a new ID.
+    pub fn end(&self) {
+        self.store.lock().remove(&self.id);
+    }
+}
+
+// A session ID: 128 bits from the operating system's random number
+// generator, in hex. Anyone who has a session's ID has the session,
+// so it mustn't be guessable, which rules out anything like a
+// counter or the time.
+pub(crate) fn random_id() -> io::Result<String> {
+    let mut bytes = [0; 16];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
@@ -0,0 +1,70 @@
+// Unix signals the server listens for. A signal handler runs in the
+// middle of whatever the process was doing, so almost nothing is safe
+// to do inside one. All ours do is set a flag, and a normal thread
+// notices it and does the real work.
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static HANGUP: AtomicBool = AtomicBool::new(false);
+static TERMINATE: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+mod unix {
+    use std::sync::atomic::Ordering;
+
+    // These numbers are the same on every Unix there is.
+    const SIGHUP: i32 = 1;
+    const SIGINT: i32 = 2;
+    const SIGTERM: i32 = 15;
+
+    extern "C" {
+        // From libc, which std already links. The return value is the
+        // previous handler, which we don't need.
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    }
+
+    extern "C" fn on_hangup(_: i32) {
+        super::HANGUP.store(true, Ordering::SeqCst);
+    }
+
+    extern "C" fn on_terminate(_: i32) {
+        super::TERMINATE.store(true, Ordering::SeqCst);
+    }
+
+    pub fn watch_hangup() {
+        unsafe {
+            signal(SIGHUP, on_hangup);
+        }
+    }
+
+    pub fn watch_termination() {
+        unsafe {
+            signal(SIGINT, on_terminate);
+            signal(SIGTERM, on_terminate);
+        }
+    }
+}
+
+// Starts catching SIGHUP instead of letting it end the process.
+// Does nothing where there are no signals.
+pub fn watch_hangup() {
+    #[cfg(unix)]
+    unix::watch_hangup();
+}
+
+// Whether a SIGHUP has arrived since the last time this was asked.
+pub fn take_hangup() -> bool {
+    HANGUP.swap(false, Ordering::SeqCst)
+}
+
+// Starts catching SIGINT (Ctrl-C) and SIGTERM, so whoever checks
+// take_termination() can clean up before the process ends. Does
+// nothing where there are no signals.
+pub fn watch_termination() {
+    #[cfg(unix)]
+    unix::watch_termination();
+}
+
+// Whether the process has been asked to stop.
+pub fn take_termination() -> bool {
+    TERMINATE.swap(false, Ordering::SeqCst)
+}
@@ -0,0 +1,78 @@
+// A very small template language for simple dynamic pages: an HTML
+// file with {{name}} where values go.
+//
+//   <h1>Hello, {{name}}!</h1>
+//
+// Values are HTML-escaped on the way in, so a name like <script> is
+// shown rather than run. {{{name}}}, with three braces, puts the
+// value in as it is, for values that are already HTML.
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::html::html_escape;
+
+// What to do with a placeholder there's no value for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unmatched {
+    // Leave {{name}} in the output, which makes a missing value easy
+    // to spot.
+    Keep,
+    // Replace it with nothing.
+    Blank,
+}
+
+// Reads the template at path and fills it in from values.
+pub fn render_file<P: AsRef<Path>>(
+    path: P,
+    values: &HashMap<String, String>,
+    unmatched: Unmatched,
+) -> io::Result<String> {
+    let template = fs::read_to_string(path)?;
+    Ok(render(&template, values, unmatched))
+}
+
+// Fills in every {{name}} and {{{name}}} in template from values.
+// Spaces just inside the braces are ignored, so {{ name }} works too.
+pub fn render(template: &str, values: &HashMap<String, String>, unmatched: Unmatched) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start..];
+
+        let raw = after.starts_with("{{{");
+        let (open, close) = if raw { ("{{{", "}}}") } else { ("{{", "}}") };
+        let end = match after[open.len()..].find(close) {
+            Some(end) => open.len() + end,
+            // No closing braces anywhere after this, so none of the
+            // rest can be a placeholder.
+            None => {
+                rest = after;
+                break;
+            }
+        };
+
+        let placeholder = &after[..end + close.len()];
+        let name = after[open.len()..end].trim();
+        // Something like {{ a b }} or braces across lines isn't a
+        // placeholder, just text that happens to have braces in it.
+        let valid = !name.is_empty()
+            && !name
+                .chars()
+                .any(|c| c.is_whitespace() || c == '{' || c == '}');
+
+        match values.get(name) {
+            Some(value) if valid && raw => output.push_str(value),
+            Some(value) if valid => output.push_str(&html_escape(value)),
+            None if valid && unmatched == Unmatched::Blank => {}
+            _ => output.push_str(placeholder),
+        }
+        rest = &after[placeholder.len()..];
+    }
+
+    output.push_str(rest);
+    output
+}
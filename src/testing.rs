@@ -0,0 +1,145 @@
+// Building the bytes of a request for Server::dispatch() in the
+// tests, so they don't have to get every \r\n right by hand:
+//
+//   let raw = RawRequest::new("POST", "/login")
+//       .header("Host", "example.com")
+//       .body("user=alice")
+//       .build();
+//   let output = server.dispatch(&raw);
+//
+// Content-Length is filled in from the body. For testing how the
+// server copes with requests that are wrong, each part of that can be
+// broken on purpose: a Content-Length that lies, no blank line after
+// the headers, bare \n line endings and so on.
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+// An empty directory of its own for a test to put files in, named
+// after the test so two running at once don't share one.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("server-test-{}-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub struct RawRequest {
+    method: String,
+    target: String,
+    version: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    // None works it out from the body, and leaves it off when there
+    // isn't one.
+    content_length: Option<Option<usize>>,
+    chunked: bool,
+    line_ending: String,
+    blank_line: bool,
+}
+
+impl RawRequest {
+    // A request for target (a path, with a query if it has one) with
+    // no headers or body, over HTTP/1.1.
+    pub fn new(method: &str, target: &str) -> RawRequest {
+        RawRequest {
+            method: method.to_string(),
+            target: target.to_string(),
+            version: String::from("HTTP/1.1"),
+            headers: Vec::new(),
+            body: Vec::new(),
+            content_length: None,
+            chunked: false,
+            line_ending: String::from("\r\n"),
+            blank_line: true,
+        }
+    }
+
+    pub fn version(mut self, version: &str) -> RawRequest {
+        self.version = version.to_string();
+        self
+    }
+
+    // Adds a header. Unlike Response::set_header() this never
+    // replaces one, since repeating a header is something worth
+    // testing too.
+    pub fn header(mut self, name: &str, value: &str) -> RawRequest {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body<B: AsRef<[u8]>>(mut self, body: B) -> RawRequest {
+        self.body = body.as_ref().to_vec();
+        self
+    }
+
+    // Sends length as the Content-Length, whatever the body is.
+    pub fn content_length(mut self, length: usize) -> RawRequest {
+        self.content_length = Some(Some(length));
+        self
+    }
+
+    // Leaves Content-Length off, even with a body.
+    pub fn without_content_length(mut self) -> RawRequest {
+        self.content_length = Some(None);
+        self
+    }
+
+    // Sends the body as a single chunk with Transfer-Encoding:
+    // chunked, in place of a Content-Length.
+    pub fn chunked(mut self) -> RawRequest {
+        self.chunked = true;
+        self
+    }
+
+    // Ends lines with ending rather than \r\n, like the bare \n some
+    // clients send.
+    pub fn line_ending(mut self, ending: &str) -> RawRequest {
+        self.line_ending = ending.to_string();
+        self
+    }
+
+    // Leaves out the blank line that ends the headers, so the server
+    // never sees the end of them.
+    pub fn without_blank_line(mut self) -> RawRequest {
+        self.blank_line = false;
+        self
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let end = &self.line_ending;
+        let mut head = format!("{} {} {}{}", self.method, self.target, self.version, end);
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}{}", name, value, end));
+        }
+
+        let mut body = self.body.clone();
+        if self.chunked {
+            head.push_str(&format!("Transfer-Encoding: chunked{}", end));
+            let mut chunks = Vec::new();
+            if !body.is_empty() {
+                chunks.extend(format!("{:x}{}", body.len(), end).into_bytes());
+                chunks.extend(&body);
+                chunks.extend(end.as_bytes());
+            }
+            chunks.extend(format!("0{}{}", end, end).into_bytes());
+            body = chunks;
+        }
+        let length = match self.content_length {
+            Some(length) => length,
+            None if self.chunked || self.body.is_empty() => None,
+            None => Some(self.body.len()),
+        };
+        if let Some(length) = length {
+            head.push_str(&format!("Content-Length: {}{}", length, end));
+        }
+        if self.blank_line {
+            head.push_str(end);
+        }
+
+        let mut raw = head.into_bytes();
+        raw.extend(body);
+        raw
+    }
+}
@@ -0,0 +1,262 @@
+use std::any::Any;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// A job is a closure a worker runs once, with the input it was
+// queued with. Boxing it lets us send any closure down the channel no
+// matter what it captures, and keeping the input apart from the
+// closure lets try_execute() hand it back when the queue is full.
+type Job = Box<dyn Task>;
+
+trait Task: Send {
+    fn run(self: Box<Self>);
+    // The job as it was queued, to get its input back out of.
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+struct Call<T, F> {
+    input: T,
+    f: F,
+}
+
+impl<T, F> Task for Call<T, F>
+where
+    T: Send + 'static,
+    F: FnOnce(T) + Send + 'static,
+{
+    fn run(self: Box<Self>) {
+        (self.f)(self.input)
+    }
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+// A fixed number of threads that take jobs off a shared queue.
+// Spawning a thread per connection would let a flood of requests
+// spawn threads until the machine falls over; with a pool, extra
+// work just waits in the queue, which only holds so many.
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+    // Wrapped in an Option so Drop can take it and close the
+    // channel, which is how the workers know to stop.
+    sender: Option<mpsc::SyncSender<Job>>,
+}
+
+// What every worker needs a handle to.
+struct Shared {
+    // Every worker pulls from the same receiver, so it goes
+    // behind a Mutex.
+    receiver: Mutex<mpsc::Receiver<Job>>,
+    // The join handles of every worker that's been started,
+    // including replacements, so Drop can wait on all of them.
+    threads: Mutex<Vec<thread::JoinHandle<()>>>,
+    // How many jobs are in the channel, not yet picked up.
+    queued: AtomicUsize,
+}
+
+impl ThreadPool {
+    // Creates a pool with size threads, and room for queue jobs to
+    // wait for one of them. With a queue of 0 a job is only taken when
+    // a worker is free to start on it.
+    //
+    // Panics if size is zero, since a pool with no threads would
+    // accept jobs and never run them.
+    pub fn new(size: usize, queue: usize) -> ThreadPool {
+        assert!(size > 0);
+
+        let (sender, receiver) = mpsc::sync_channel(queue);
+
+        let shared = Arc::new(Shared {
+            receiver: Mutex::new(receiver),
+            threads: Mutex::new(Vec::with_capacity(size)),
+            queued: AtomicUsize::new(0),
+        });
+
+        for id in 0..size {
+            spawn_worker(id, Arc::clone(&shared));
+        }
+
+        ThreadPool {
+            shared,
+            sender: Some(sender),
+        }
+    }
+
+    // Queues f to be run by the next free worker, waiting for room in
+    // the queue if it's full.
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shared.queued.fetch_add(1, Ordering::SeqCst);
+        let job = Box::new(Call {
+            input: (),
+            f: move |()| f(),
+        });
+
+        self.sender.as_ref().unwrap().send(job).unwrap();
+    }
+
+    // Queues f to be run on input by the next free worker, unless the
+    // queue is full. Then nothing is queued and input is handed back,
+    // so whoever is adding work can do something else with it rather
+    // than wait for the pool to catch up.
+    pub fn try_execute<T, F>(&self, input: T, f: F) -> Result<(), T>
+    where
+        T: Send + 'static,
+        F: FnOnce(T) + Send + 'static,
+    {
+        self.shared.queued.fetch_add(1, Ordering::SeqCst);
+        let job: Job = Box::new(Call { input, f });
+
+        match self.sender.as_ref().unwrap().try_send(job) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(job)) => {
+                self.shared.queued.fetch_sub(1, Ordering::SeqCst);
+                match job.into_any().downcast::<Call<T, F>>() {
+                    Ok(call) => Err(call.input),
+                    Err(_) => unreachable!("a job came back as something else"),
+                }
+            }
+            Err(TrySendError::Disconnected(_)) => unreachable!("the workers outlive the pool"),
+        }
+    }
+
+    // How many jobs are waiting for a worker right now.
+    pub fn queued(&self) -> usize {
+        self.shared.queued.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // Dropping the sender closes the channel. Once the queue is
+        // empty every recv() fails and the workers leave their loops.
+        drop(self.sender.take());
+
+        // A worker that dies now still pushes its replacement before
+        // it exits, and we only finish joining it after that, so
+        // popping until the list is empty catches every thread.
+        loop {
+            let thread = lock(&self.shared.threads).pop();
+            match thread {
+                Some(thread) => {
+                    let _ = thread.join();
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+fn spawn_worker(id: usize, shared: Arc<Shared>) {
+    let worker_shared = Arc::clone(&shared);
+
+    let spawned = thread::Builder::new()
+        .name(format!("worker-{}", id))
+        .spawn(move || {
+            // If anything unwinds out of this thread, the sentinel's
+            // Drop runs on the way out and starts a replacement, so
+            // the pool never quietly shrinks.
+            let _sentinel = Sentinel {
+                id,
+                shared: Arc::clone(&worker_shared),
+            };
+
+            loop {
+                // The lock is only held while waiting for a job, the
+                // temporary guard is dropped before the job runs.
+                let message = lock(&worker_shared.receiver).recv();
+
+                match message {
+                    Ok(job) => {
+                        worker_shared.queued.fetch_sub(1, Ordering::SeqCst);
+                        job.run()
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+
+    match spawned {
+        Ok(thread) => {
+            // Drop the handles of workers that have already died so
+            // repeated failures don't pile them up.
+            let mut threads = lock(&shared.threads);
+            threads.retain(|thread| !thread.is_finished());
+            threads.push(thread);
+        }
+        Err(e) => error!("couldn't start worker {}: {}", id, e),
+    }
+}
+
+struct Sentinel {
+    id: usize,
+    shared: Arc<Shared>,
+}
+
+impl Drop for Sentinel {
+    fn drop(&mut self) {
+        // A worker leaving normally is the pool shutting down. Only
+        // a panic means the worker died and needs replacing.
+        if thread::panicking() {
+            error!("worker {} died, starting a replacement", self.id);
+            spawn_worker(self.id, Arc::clone(&self.shared));
+        }
+    }
+}
+
+// A worker panicking can't poison these locks while it matters
+// (nothing panics while holding them), but if it ever did, the data
+// behind them is still fine to use.
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_full_queue_hands_the_input_back() {
+        let pool = ThreadPool::new(1, 1);
+        let (started, running) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+
+        // Keeps the only worker busy until it's released.
+        pool.execute(move || {
+            started.send(()).unwrap();
+            let _ = released.recv();
+        });
+        running.recv().unwrap();
+
+        assert_eq!(pool.try_execute(1, |_| {}), Ok(()));
+        assert_eq!(pool.queued(), 1);
+        assert_eq!(pool.try_execute(2, |_| {}), Err(2));
+        assert_eq!(pool.queued(), 1);
+
+        release.send(()).unwrap();
+        drop(pool);
+    }
+
+    #[test]
+    fn queued_jobs_get_their_input() {
+        let pool = ThreadPool::new(2, 8);
+        let (sender, results) = mpsc::channel();
+        for i in 0..8 {
+            let sender = sender.clone();
+            pool.try_execute(i, move |i| sender.send(i * 10).unwrap())
+                .unwrap();
+        }
+        drop(pool);
+        drop(sender);
+        let mut results: Vec<i32> = results.iter().collect();
+        results.sort();
+        assert_eq!(results, (0..8).map(|i| i * 10).collect::<Vec<_>>());
+    }
+}
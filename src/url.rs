@@ -0,0 +1,57 @@
+// Percent-encoding, the %XX escapes URLs use for bytes that
+// aren't allowed in them or would mean something else there.
+
+// Decodes %XX escapes. Returns None if an escape is cut short,
+// isn't hex, or the result isn't valid UTF-8.
+pub fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            // from_str_radix would happily take "+f", so check
+            // the digits ourselves first.
+            let hex = input.get(i + 1..i + 3)?;
+            if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+// Encodes a path for putting in a URL we send out, like a link or a
+// Location header. Anything that isn't a plain letter, digit, one of
+// - . _ ~ or the / between segments gets escaped, so names with
+// spaces, #, ? or non-ASCII in them come back to us intact.
+pub fn percent_encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for &byte in path.as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn awkward_names_are_encoded_and_come_back_intact() {
+        let encoded = percent_encode_path("/docs/my file #1.txt");
+        assert_eq!(encoded, "/docs/my%20file%20%231.txt");
+        assert_eq!(percent_decode(&encoded).unwrap(), "/docs/my file #1.txt");
+    }
+}
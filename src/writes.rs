@@ -0,0 +1,159 @@
+// Changing files under the static root over HTTP. None of this is
+// reachable unless Config::allow_writes is on. Fallback roots are
+// never written to, only the static root itself.
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::conditional::{self, Outcome, Resource};
+use crate::config::Config;
+use crate::files::{self, ResolveError};
+use crate::httpdate;
+use crate::request::Request;
+use crate::response::Response;
+use crate::url;
+
+// Makes temporary file names unique between threads writing at once.
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// PUT: stores the request body as the file at the request path,
+// creating any directories on the way. Answers 201 if the file is
+// new and 204 if it replaced one, or fails with the error status.
+//
+// The body is written to a temporary file next to the target and
+// then renamed over it. A rename within a directory is atomic, so
+// anyone reading the file sees either the whole old version or the
+// whole new one, never half of each.
+pub fn put(config: &Config, request: &Request) -> Result<Response, u16> {
+    let file = match files::resolve(config, &config.static_root.path, &request.path) {
+        Ok(file) => file,
+        Err(ResolveError::Forbidden) => return Err(403),
+        Err(ResolveError::BadPath) => return Err(400),
+    };
+    // A path ending in / (or the root itself) names a directory, not
+    // a file we could write.
+    if request.path.ends_with('/') || file == config.static_root.path {
+        return Err(405);
+    }
+
+    let existing = fs::metadata(&file).ok();
+    if existing.as_ref().is_some_and(|metadata| metadata.is_dir()) {
+        return Err(409);
+    }
+
+    // If-Match lets a client say "only if nobody's changed it since
+    // I looked", and If-None-Match: * "only if it doesn't exist yet".
+    let etag = existing.as_ref().and_then(|_| files::etag_of(&file));
+    let resource = Resource {
+        exists: existing.is_some(),
+        etag: etag.as_deref(),
+        last_modified: existing
+            .as_ref()
+            .and_then(|metadata| metadata.modified().ok()),
+    };
+    if conditional::evaluate(request, &resource) != Outcome::Proceed {
+        return Err(412);
+    }
+
+    let parent = file.parent().ok_or(405u16)?;
+    // Fails if part of the path is already a file, which is the
+    // client's mistake rather than ours.
+    fs::create_dir_all(parent).map_err(|_| 409u16)?;
+    write_atomically(parent, &file, &request.body).map_err(|e| {
+        error!("couldn't write {}: {}", file.display(), e);
+        500u16
+    })?;
+
+    let mut response = if existing.is_some() {
+        Response::new(204)
+    } else {
+        let mut response = Response::new(201);
+        if let Some(path) = url::percent_decode(&request.path) {
+            response.set_header("Location", &url::percent_encode_path(&path));
+        }
+        response
+    };
+    // The new version's validators, so the client can make its next
+    // conditional request without fetching the file first.
+    response.set_header("ETag", &files::etag(&request.body));
+    if let Ok(metadata) = fs::metadata(&file) {
+        if let Ok(modified) = metadata.modified() {
+            response.set_header("Last-Modified", &httpdate::format(modified));
+        }
+    }
+    Ok(response)
+}
+
+// DELETE: removes the file at the request path. Answers 204 if it
+// was there and 404 if it wasn't. Directories are only removed when
+// Config::delete_directories is on, and only if they're empty.
+//
+// Deleting a file someone else is downloading is fine: the server
+// already has it open, and on Unix an open file lives on until it's
+// closed, so they still get all of it.
+pub fn delete(config: &Config, request: &Request) -> Result<Response, u16> {
+    let file = match files::resolve(config, &config.static_root.path, &request.path) {
+        Ok(file) => file,
+        Err(ResolveError::Forbidden) => return Err(403),
+        Err(ResolveError::BadPath) => return Err(400),
+    };
+    // However it's configured, the root itself stays.
+    if file == config.static_root.path {
+        return Err(405);
+    }
+
+    let metadata = fs::metadata(&file).map_err(|_| 404u16)?;
+    let etag = files::etag_of(&file);
+    let resource = Resource {
+        exists: true,
+        etag: etag.as_deref(),
+        last_modified: metadata.modified().ok(),
+    };
+    if conditional::evaluate(request, &resource) != Outcome::Proceed {
+        return Err(412);
+    }
+
+    let result = if metadata.is_dir() {
+        if !config.delete_directories {
+            return Err(405);
+        }
+        fs::remove_dir(&file)
+    } else {
+        fs::remove_file(&file)
+    };
+    match result {
+        Ok(()) => Ok(Response::new(204)),
+        // Someone else deleted it first.
+        Err(e) if e.kind() == ErrorKind::NotFound => Err(404),
+        // Most likely a directory that isn't empty.
+        Err(_) if metadata.is_dir() => Err(409),
+        Err(e) => {
+            error!("couldn't delete {}: {}", file.display(), e);
+            Err(500)
+        }
+    }
+}
+
+fn write_atomically(dir: &Path, file: &Path, contents: &[u8]) -> std::io::Result<()> {
+    // A dotfile, so it isn't served (or listed) while it's being
+    // written.
+    let temp = dir.join(format!(
+        ".upload-{}-{}.tmp",
+        process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = File::create(&temp)
+        .and_then(|mut out| {
+            out.write_all(contents)?;
+            out.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp, file));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}